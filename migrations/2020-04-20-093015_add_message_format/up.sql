@@ -0,0 +1,2 @@
+-- format of the message body: text, markdown or html
+ALTER TABLE messages ADD COLUMN format Text NOT NULL DEFAULT 'text';
@@ -0,0 +1,2 @@
+-- for listing messages of a receiver, newest first
+CREATE INDEX messages_receiver_time_idx ON messages (receiver_id, created_time DESC, id DESC);
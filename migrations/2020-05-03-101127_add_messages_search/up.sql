@@ -0,0 +1,4 @@
+-- full-text search over title and body, by an index on the same expression
+-- `search_messages` queries, so there's no column to keep in sync or in schema.rs
+CREATE INDEX messages_search_idx ON messages
+    USING GIN (to_tsvector('pg_catalog.simple', title || ' ' || coalesce(body, '')));
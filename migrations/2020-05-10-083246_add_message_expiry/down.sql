@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE messages DROP COLUMN revoked_time;
+ALTER TABLE messages DROP COLUMN expires_at;
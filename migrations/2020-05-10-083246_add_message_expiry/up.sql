@@ -0,0 +1,3 @@
+-- messages are gone after expiry or revocation
+ALTER TABLE messages ADD COLUMN expires_at BIGINT;
+ALTER TABLE messages ADD COLUMN revoked_time BIGINT;
@@ -0,0 +1,10 @@
+-- views of the message detail page
+CREATE TABLE message_views (
+    id BIGSERIAL PRIMARY KEY,
+    message_id UUID NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    viewed_time BIGINT NOT NULL,
+    -- coarse client type from UA: wechat, mobile, desktop or other
+    client Text NOT NULL
+);
+
+CREATE INDEX message_views_message_idx ON message_views (message_id);
@@ -0,0 +1,17 @@
+-- resend unread messages, to the same receiver then down a list of receivers
+CREATE TABLE escalations (
+    id BIGSERIAL PRIMARY KEY,
+    message_id UUID NOT NULL REFERENCES messages (id) ON DELETE CASCADE,
+    -- the first resend goes to the receiver of the message, the n-th (n > 1) to
+    -- receivers[n - 1] (1-based), the last one repeated for any further steps
+    receivers Text[] NOT NULL,
+    step INTEGER NOT NULL DEFAULT 0,
+    max_steps INTEGER NOT NULL,
+    interval_seconds BIGINT NOT NULL,
+    next_time BIGINT NOT NULL,
+    -- pending, seen, cancelled or exhausted
+    status Text NOT NULL DEFAULT 'pending'
+);
+
+CREATE INDEX escalations_pending_idx ON escalations (next_time) WHERE status = 'pending';
+CREATE INDEX escalations_message_idx ON escalations (message_id);
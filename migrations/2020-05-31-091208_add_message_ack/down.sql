@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE messages DROP COLUMN ack_webhook;
+ALTER TABLE messages DROP COLUMN acked_by;
+ALTER TABLE messages DROP COLUMN acked_time;
@@ -0,0 +1,5 @@
+-- explicit acknowledgement of a message by one of its receivers
+ALTER TABLE messages ADD COLUMN acked_time BIGINT;
+ALTER TABLE messages ADD COLUMN acked_by Text;
+-- notified when the message is acknowledged
+ALTER TABLE messages ADD COLUMN ack_webhook Text;
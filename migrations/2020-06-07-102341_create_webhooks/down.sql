@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX messages_wechat_msg_id_idx;
+ALTER TABLE messages DROP COLUMN wechat_msg_id;
+DROP TABLE webhook_deliveries;
+DROP TABLE webhooks;
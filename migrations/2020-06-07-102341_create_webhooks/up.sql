@@ -0,0 +1,42 @@
+-- webhooks registered by senders for message lifecycle events
+CREATE TABLE webhooks (
+    id BIGSERIAL PRIMARY KEY,
+    receiver_id Text NOT NULL,
+    url Text NOT NULL,
+    -- events to deliver, all of them if empty
+    events Text[] NOT NULL DEFAULT '{}',
+    created_time BIGINT NOT NULL
+);
+
+CREATE INDEX webhooks_receiver_idx ON webhooks (receiver_id);
+
+-- queue and log of webhook deliveries
+CREATE TABLE webhook_deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    -- null for the ack_webhook of a message
+    webhook_id BIGINT REFERENCES webhooks (id) ON DELETE CASCADE,
+    -- whose send key signs the payload
+    receiver_id Text NOT NULL,
+    -- not a foreign key, as messages failed to push are never saved but
+    -- reported anyway; deleted along with the message by retention
+    message_id UUID NOT NULL,
+    url Text NOT NULL,
+    event Text NOT NULL,
+    payload Text NOT NULL,
+    -- pending, delivered or failed
+    status Text NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_time BIGINT NOT NULL,
+    created_time BIGINT NOT NULL,
+    delivered_time BIGINT,
+    last_status_code INTEGER,
+    last_error Text
+);
+
+CREATE INDEX webhook_deliveries_pending_idx ON webhook_deliveries (next_time) WHERE status = 'pending';
+CREATE INDEX webhook_deliveries_webhook_idx ON webhook_deliveries (webhook_id, id);
+CREATE INDEX webhook_deliveries_message_idx ON webhook_deliveries (message_id);
+
+-- to match the TEMPLATESENDJOBFINISH events
+ALTER TABLE messages ADD COLUMN wechat_msg_id BIGINT;
+CREATE INDEX messages_wechat_msg_id_idx ON messages (wechat_msg_id);
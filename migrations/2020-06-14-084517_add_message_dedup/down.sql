@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX messages_dedup_open_idx;
+ALTER TABLE messages DROP COLUMN dedup_closed;
+ALTER TABLE messages DROP COLUMN duplicates;
+ALTER TABLE messages DROP COLUMN dedup_until;
+ALTER TABLE messages DROP COLUMN dedup_key;
@@ -0,0 +1,9 @@
+-- identical alerts until the end of the window are collapsed into the first message
+ALTER TABLE messages ADD COLUMN dedup_key Text;
+ALTER TABLE messages ADD COLUMN dedup_until BIGINT;
+ALTER TABLE messages ADD COLUMN duplicates INTEGER NOT NULL DEFAULT 0;
+-- set once the window is over and the follow-up is pushed
+ALTER TABLE messages ADD COLUMN dedup_closed BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX messages_dedup_open_idx ON messages (dedup_until)
+    WHERE dedup_key IS NOT NULL AND NOT dedup_closed;
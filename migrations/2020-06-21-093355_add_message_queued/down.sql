@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX messages_queued_idx;
+ALTER TABLE messages DROP COLUMN queued;
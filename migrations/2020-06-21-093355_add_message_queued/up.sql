@@ -0,0 +1,4 @@
+-- saved but not pushed yet as the daily quota of wechat api is reached
+ALTER TABLE messages ADD COLUMN queued BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX messages_queued_idx ON messages (created_time) WHERE queued;
@@ -0,0 +1,8 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX messages_digest_idx;
+DROP INDEX messages_held_idx;
+ALTER TABLE messages DROP COLUMN digest_id;
+ALTER TABLE messages DROP COLUMN held_until;
+ALTER TABLE messages DROP COLUMN priority;
+DROP TABLE digests;
+DROP TABLE receiver_preferences;
@@ -0,0 +1,27 @@
+-- delivery preferences of receivers
+CREATE TABLE receiver_preferences (
+    receiver_id Text PRIMARY KEY,
+    -- IANA time zone of the quiet hours
+    timezone Text NOT NULL DEFAULT 'Asia/Shanghai',
+    -- quiet hours in minutes after midnight, may wrap around midnight
+    quiet_start INTEGER,
+    quiet_end INTEGER,
+    -- messages below this priority are held in quiet hours
+    min_priority Text NOT NULL DEFAULT 'high',
+    updated_time BIGINT NOT NULL
+);
+
+-- held messages are pushed together in a digest
+CREATE TABLE digests (
+    id UUID PRIMARY KEY,
+    receiver_id Text NOT NULL,
+    created_time BIGINT NOT NULL
+);
+
+-- low, normal, high or critical
+ALTER TABLE messages ADD COLUMN priority Text NOT NULL DEFAULT 'normal';
+ALTER TABLE messages ADD COLUMN held_until BIGINT;
+ALTER TABLE messages ADD COLUMN digest_id UUID REFERENCES digests (id) ON DELETE SET NULL;
+
+CREATE INDEX messages_held_idx ON messages (held_until) WHERE held_until IS NOT NULL;
+CREATE INDEX messages_digest_idx ON messages (digest_id);
@@ -0,0 +1,2 @@
+-- push messages in a digest every this many minutes, off if null
+ALTER TABLE receiver_preferences ADD COLUMN digest_interval INTEGER;
@@ -0,0 +1,6 @@
+-- the official account each receiver subscribed to, open ids are per account
+CREATE TABLE receiver_accounts (
+    receiver_id Text PRIMARY KEY,
+    account Text NOT NULL,
+    updated_time BIGINT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE messages DROP COLUMN channel;
+DROP TABLE delivery_channels;
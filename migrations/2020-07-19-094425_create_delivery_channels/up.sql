@@ -0,0 +1,15 @@
+-- ordered fallback chain of delivery channels of receivers
+CREATE TABLE delivery_channels (
+    receiver_id Text NOT NULL,
+    position INTEGER NOT NULL,
+    -- template, custom, wecom or webhook
+    kind Text NOT NULL,
+    -- name of the official account or WeCom app
+    account Text,
+    -- WeCom user id, or url of the webhook
+    target Text,
+    PRIMARY KEY (receiver_id, position)
+);
+
+-- the channel the message was pushed by
+ALTER TABLE messages ADD COLUMN channel Text;
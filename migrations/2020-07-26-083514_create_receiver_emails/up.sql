@@ -0,0 +1,8 @@
+-- verified email addresses of receivers
+CREATE TABLE receiver_emails (
+    receiver_id Text PRIMARY KEY,
+    address Text NOT NULL,
+    -- also email messages taken by other channels, not only as a fallback
+    always BOOLEAN NOT NULL DEFAULT FALSE,
+    verified_time BIGINT NOT NULL
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE hook_mappings;
+DROP INDEX receiver_accounts_sendkey;
+ALTER TABLE receiver_accounts DROP COLUMN sendkey;
@@ -0,0 +1,14 @@
+-- to find the receiver of `/hook/{sendkey}/{mapping}`, set when the account is saved
+-- or the receiver logs in, and for earlier receivers at startup
+ALTER TABLE receiver_accounts ADD COLUMN sendkey Text;
+CREATE UNIQUE INDEX receiver_accounts_sendkey ON receiver_accounts (sendkey);
+
+-- mappings of incoming json to messages, by receiver and name
+CREATE TABLE hook_mappings (
+    receiver_id Text NOT NULL,
+    name Text NOT NULL,
+    -- the mapping as json
+    mapping Text NOT NULL,
+    updated_time BIGINT NOT NULL,
+    PRIMARY KEY (receiver_id, name)
+);
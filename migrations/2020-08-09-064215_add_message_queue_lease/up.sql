@@ -0,0 +1,3 @@
+-- queued messages claimed by `jobs::queue` are not claimed again till then,
+-- so they are pushed later if the job dies pushing them
+ALTER TABLE messages ADD COLUMN queue_lease BIGINT;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX messages_sender_idx;
+ALTER TABLE messages DROP COLUMN sender_id;
@@ -0,0 +1,4 @@
+-- the receiver whose send key sent the message, for retention rules by sender.
+-- Messages sent by open id alone have none.
+ALTER TABLE messages ADD COLUMN sender_id Text;
+CREATE INDEX messages_sender_idx ON messages (sender_id, created_time);
@@ -0,0 +1,3 @@
+-- only the sha256 of send keys is saved from now on, the plain ones are
+-- dropped and saved again as hashes at startup
+UPDATE receiver_accounts SET sendkey = NULL;
@@ -0,0 +1,33 @@
+//! Ack tokens authorise acknowledging a message from its detail page.
+//!
+//! Every receiver a message is pushed to gets their own token in the detail
+//! url, so a valid token also tells who acknowledged.
+use uuid::Uuid;
+
+use crate::errors::{Error, Result};
+use crate::utils::{hmac_sha256_hex, secure_eq};
+
+pub fn ack_token(secret_key: &str, message_id: &Uuid, receiver: &str) -> String {
+    hmac_sha256_hex(secret_key, &format!("ack:{}:{}", message_id, receiver))
+}
+
+pub fn verify(secret_key: &str, message_id: &Uuid, receiver: &str, token: &str) -> Result<()> {
+    match secure_eq(&ack_token(secret_key, message_id, receiver), token) {
+        true => Ok(()),
+        false => Err(Error::Unauthorized("Invalid ack token".to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ack_token() {
+        let id = Uuid::new_v4();
+        let token = ack_token("secret", &id, "open_id");
+        assert!(verify("secret", &id, "open_id", &token).is_ok());
+        assert!(verify("secret", &id, "other_open_id", &token).is_err());
+        assert!(verify("secret", &Uuid::new_v4(), "open_id", &token).is_err());
+    }
+}
@@ -1,8 +1,16 @@
 use config::{Config as ConfigMod, ConfigError, File};
 use log;
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
+use std::net::IpAddr;
 
+use crate::wechat::wecom::MessageType;
+
+/// Name of the account configured in `[wechat]`.
+pub const DEFAULT_ACCOUNT: &str = "default";
+
+/// An official account.
 #[derive(Deserialize, Debug, Clone)]
 pub struct WechatConfig {
     pub app_id: String,
@@ -12,12 +20,278 @@ pub struct WechatConfig {
     pub detail_url: String,
 }
 
+/// A WeCom self-built app, pushing application messages to users of the corp.
+#[derive(Deserialize, Debug, Clone)]
+pub struct WecomConfig {
+    pub corp_id: String,
+    /// secret of the app
+    pub corp_secret: String,
+    pub agent_id: i64,
+    /// text, textcard (default) or markdown
+    #[serde(default)]
+    pub message_type: MessageType,
+}
+
+impl WecomConfig {
+    /// Saved as the app id of messages pushed by the app.
+    pub fn app_id(&self) -> String {
+        format!("wecom:{}:{}", self.corp_id, self.agent_id)
+    }
+}
+
+/// Turns the json posted to `/hook/{sendkey}/{name}` into a message.
+///
+/// Each field is a template with `{{ <jsonpath> }}` placeholders, e.g.
+/// `"[{{ $.state }}] {{ $.ruleName }}"`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HookMapping {
+    pub title: String,
+    pub body: Option<String>,
+    pub url: Option<String>,
+    /// rendered to a priority, or to a key of `priorities`
+    pub priority: Option<String>,
+    /// priorities by rendered value, e.g. `alerting = "high"`
+    #[serde(default)]
+    pub priorities: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct RetentionRule {
+    /// delete messages older than this
+    pub delete_after_days: Option<u64>,
+    /// blank sender IP and UA of messages older than this
+    pub anonymise_after_days: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// the rule for all messages except those of `senders`
+    pub default: RetentionRule,
+    /// rules by sender, i.e. the open id of the receiver whose send key sent the messages.
+    /// Messages sent without a send key follow the default rule.
+    pub senders: HashMap<String, RetentionRule>,
+    /// how often to purge, must be positive
+    pub interval_seconds: u64,
+    pub batch_size: i64,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            default: RetentionRule::default(),
+            senders: HashMap::new(),
+            interval_seconds: 60 * 60,
+            batch_size: 1000,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct EscalationConfig {
+    /// how often to look for due escalations, must be positive
+    pub poll_seconds: u64,
+    /// max escalations handled per poll
+    pub batch_size: i64,
+}
+
+impl Default for EscalationConfig {
+    fn default() -> Self {
+        EscalationConfig {
+            poll_seconds: 30,
+            batch_size: 100,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// how often to look for due deliveries, must be positive
+    pub poll_seconds: u64,
+    /// max deliveries attempted per poll
+    pub batch_size: i64,
+    /// a delivery fails after this many attempts
+    pub max_attempts: i32,
+    /// delay before the first retry, doubled for each further one
+    pub retry_seconds: i64,
+    pub timeout_seconds: u64,
+    /// max webhooks registered by a sender
+    pub max_webhooks: i64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        WebhookConfig {
+            poll_seconds: 10,
+            batch_size: 100,
+            max_attempts: 6,
+            retry_seconds: 30,
+            timeout_seconds: 10,
+            max_webhooks: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DedupConfig {
+    /// how often to look for closed dedup windows, must be positive
+    pub poll_seconds: u64,
+    /// max follow-ups pushed per poll
+    pub batch_size: i64,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        DedupConfig {
+            poll_seconds: 30,
+            batch_size: 100,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RateLimitRule {
+    /// tokens refilled per second
+    pub rate: f64,
+    /// max tokens in a bucket
+    pub burst: u32,
+}
+
+/// Rate limits by bucket, no limit if a rule is absent.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub sender: Option<RateLimitRule>,
+    pub receiver: Option<RateLimitRule>,
+    pub ip: Option<RateLimitRule>,
+    /// verification emails, per open id and per address
+    pub email: Option<RateLimitRule>,
+    /// reverse proxies whose `X-Forwarded-For` is trusted for the client ip
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct QuotaConfig {
+    /// daily limits by wechat api, e.g. `message/template/send`, to warn about
+    pub limits: HashMap<String, u64>,
+    /// warn when usage reaches these ratios of the limit
+    pub warn_ratios: Vec<f64>,
+    /// how often to try pushing messages queued when the quota was reached, must be positive
+    pub poll_seconds: u64,
+    /// max queued messages pushed per poll
+    pub batch_size: i64,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        QuotaConfig {
+            limits: HashMap::new(),
+            warn_ratios: vec![0.8, 0.95],
+            poll_seconds: 60,
+            batch_size: 100,
+        }
+    }
+}
+
+/// The smtp server emails are sent by, email is disabled if `host` is empty.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// defaults to 465 for `tls`, 587 for `starttls` and 25 for `none`
+    pub port: Option<u16>,
+    /// `tls`, `starttls` or `none`, the latter only for local sinks
+    pub tls: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// the From header, e.g. `wxpush <noreply@example.com>`
+    pub from: String,
+    pub timeout_seconds: u64,
+    /// minutes a verification code is valid for
+    pub code_minutes: u64,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        SmtpConfig {
+            host: String::new(),
+            port: None,
+            tls: "starttls".to_owned(),
+            username: None,
+            password: None,
+            from: String::new(),
+            timeout_seconds: 10,
+            code_minutes: 10,
+        }
+    }
+}
+
+impl SmtpConfig {
+    pub fn enabled(&self) -> bool {
+        !self.host.is_empty()
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DigestConfig {
+    /// how often to push digests of held messages, must be positive
+    pub poll_seconds: u64,
+    /// max held messages taken per poll
+    pub batch_size: i64,
+}
+
+impl Default for DigestConfig {
+    fn default() -> Self {
+        DigestConfig {
+            poll_seconds: 60,
+            batch_size: 500,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     pub root_url: String,
+    /// the absolute http(s) url `root_url` is reached at, to link pages of the server in pushes
+    pub public_url: String,
     pub redis_url: String,
     pub postgres_url: String,
+    /// key to sign tokens issued by the server
+    pub secret_key: String,
+    /// bearer token for the admin api, which is disabled if absent
+    pub admin_key: Option<String>,
+    /// the default account
     pub wechat: WechatConfig,
+    /// more official accounts by name, served at `/callback/{name}`
+    #[serde(default)]
+    pub accounts: HashMap<String, WechatConfig>,
+    /// WeCom apps by name, pushing to receivers mapped to them or by senders naming them
+    #[serde(default)]
+    pub wecom: HashMap<String, WecomConfig>,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub escalation: EscalationConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
+    #[serde(default)]
+    pub dedup: DedupConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub quota: QuotaConfig,
+    #[serde(default)]
+    pub digest: DigestConfig,
+    #[serde(default)]
+    pub smtp: SmtpConfig,
+    /// mappings for `/hook/{sendkey}/{name}` by name, unless the receiver saved one
+    #[serde(default)]
+    pub hook_mappings: HashMap<String, HookMapping>,
 }
 
 impl Config {
@@ -35,15 +309,105 @@ impl Config {
         }
 
         s.try_into().and_then(|mut c: Self| {
-            c.check();
+            c.check()?;
             log::info!("Config loaded.");
             Ok(c)
         })
     }
 
     /// Check config validation, trims url, etc.
-    fn check(&mut self) {
+    fn check(&mut self) -> Result<(), ConfigError> {
         self.root_url = self.root_url.trim_end_matches("/").to_owned();
+        self.public_url = self.public_url.trim_end_matches("/").to_owned();
+        match url::Url::parse(&self.public_url) {
+            Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+            _ => {
+                return Err(ConfigError::Message(
+                    "public_url must be an absolute http(s) url".to_owned(),
+                ))
+            }
+        }
         self.wechat.detail_url = self.wechat.detail_url.trim_end_matches("/").to_owned();
+        if self.accounts.remove(DEFAULT_ACCOUNT).is_some() {
+            log::warn!("Account {} is taken by [wechat], ignored", DEFAULT_ACCOUNT);
+        }
+        for account in self.accounts.values_mut() {
+            account.detail_url = account.detail_url.trim_end_matches("/").to_owned();
+        }
+        let accounts = &self.accounts;
+        self.wecom.retain(|name, _| {
+            let taken = name == DEFAULT_ACCOUNT || accounts.contains_key(name);
+            if taken {
+                log::warn!("WeCom app {} is named after an account, ignored", name);
+            }
+            !taken
+        });
+        let intervals = [
+            (
+                "retention.interval_seconds",
+                self.retention.interval_seconds,
+            ),
+            ("escalation.poll_seconds", self.escalation.poll_seconds),
+            ("webhook.poll_seconds", self.webhook.poll_seconds),
+            ("dedup.poll_seconds", self.dedup.poll_seconds),
+            ("quota.poll_seconds", self.quota.poll_seconds),
+            ("digest.poll_seconds", self.digest.poll_seconds),
+        ];
+        if let Some((name, _)) = intervals.iter().find(|(_, seconds)| *seconds == 0) {
+            return Err(ConfigError::Message(format!("{} must be positive", name)));
+        }
+        Ok(())
+    }
+
+    /// The official account by name, `default` for the one in `[wechat]`.
+    pub fn account(&self, name: &str) -> Option<&WechatConfig> {
+        match name {
+            DEFAULT_ACCOUNT => Some(&self.wechat),
+            _ => self.accounts.get(name),
+        }
+    }
+
+    /// The WeCom app by name.
+    pub fn wecom_app(&self, name: &str) -> Option<&WecomConfig> {
+        self.wecom.get(name)
+    }
+
+    /// Names of all the accounts, including WeCom apps.
+    pub fn account_names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(DEFAULT_ACCOUNT)
+            .chain(self.accounts.keys().map(String::as_str))
+            .chain(self.wecom.keys().map(String::as_str))
+    }
+
+    /// App id saved with messages pushed by the account or WeCom app.
+    pub fn app_id(&self, name: &str) -> Option<String> {
+        match self.account(name) {
+            Some(account) => Some(account.app_id.clone()),
+            None => self.wecom_app(name).map(WecomConfig::app_id),
+        }
+    }
+
+    /// Name of the account with the app id, the default one if not configured any more.
+    pub fn account_by_app_id(&self, app_id: &str) -> &str {
+        self.account_names()
+            .find(|name| self.app_id(name).as_deref() == Some(app_id))
+            .unwrap_or(DEFAULT_ACCOUNT)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_public_url() {
+        let mut config = Config::new().unwrap();
+        config.public_url = "https://push.example.com/api/v2/".to_owned();
+        assert!(config.check().is_ok());
+        assert_eq!(config.public_url, "https://push.example.com/api/v2");
+        for url in ["/api/v2", "", "ftp://push.example.com"].iter() {
+            config.public_url = url.to_string();
+            assert!(config.check().is_err(), "{}", url);
+        }
     }
 }
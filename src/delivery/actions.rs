@@ -0,0 +1,58 @@
+use crate::errors::Result;
+use crate::models;
+
+use diesel::prelude::*;
+
+/// The fallback chain of the receiver, in order.
+pub fn find_channels(receiver: &str, con: &PgConnection) -> Result<Vec<models::DeliveryChannel>> {
+    use crate::schema::delivery_channels::dsl::*;
+    let found = delivery_channels
+        .filter(receiver_id.eq(receiver))
+        .order(position)
+        .load::<models::DeliveryChannel>(con)?;
+    Ok(found)
+}
+
+/// Replace the fallback chain of the receiver, the default one if empty.
+pub fn save_channels(
+    receiver: &str,
+    channels: &[models::DeliveryChannel],
+    con: &PgConnection,
+) -> Result<()> {
+    use crate::schema::delivery_channels::dsl::*;
+    con.transaction(|| {
+        diesel::delete(delivery_channels.filter(receiver_id.eq(receiver))).execute(con)?;
+        diesel::insert_into(delivery_channels)
+            .values(channels)
+            .execute(con)?;
+        Ok(())
+    })
+}
+
+/// The verified email address of the receiver.
+pub fn find_email(receiver: &str, con: &PgConnection) -> Result<Option<models::ReceiverEmail>> {
+    use crate::schema::receiver_emails::dsl::*;
+    let mut found = receiver_emails
+        .filter(receiver_id.eq(receiver))
+        .limit(1)
+        .load::<models::ReceiverEmail>(con)?;
+    Ok(found.pop())
+}
+
+pub fn save_email(email: &models::ReceiverEmail, con: &PgConnection) -> Result<()> {
+    use crate::schema::receiver_emails::dsl::*;
+    diesel::insert_into(receiver_emails)
+        .values(email)
+        .on_conflict(receiver_id)
+        .do_update()
+        .set(email)
+        .execute(con)?;
+    Ok(())
+}
+
+/// Forget the email address of the receiver, returning whether there was one.
+pub fn delete_email(receiver: &str, con: &PgConnection) -> Result<bool> {
+    use crate::schema::receiver_emails::dsl::*;
+    let deleted = diesel::delete(receiver_emails.filter(receiver_id.eq(receiver))).execute(con)?;
+    Ok(deleted > 0)
+}
@@ -0,0 +1,163 @@
+use actix_web::web;
+use async_trait::async_trait;
+use serde_json::json;
+use std::time::Duration;
+
+use super::{ChannelKind, DeliveryChannel, DeliveryError};
+use crate::email;
+use crate::html::body::BodyFormat;
+use crate::quota::{self, Api, Push};
+use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+use crate::wechat::template_message::{apis, NewMessage};
+use crate::wechat::{custom_message, wecom, TokenManager};
+
+fn token_manager<'a>(
+    state: &'a AppState,
+    account: &str,
+) -> Result<&'a futures::lock::Mutex<TokenManager>, DeliveryError> {
+    state
+        .token_managers
+        .get(account)
+        .ok_or_else(|| DeliveryError::Misconfigured(format!("Unknown account {}", account)))
+}
+
+/// Template messages of an official account.
+pub struct TemplateMessage {
+    pub account: String,
+}
+
+#[async_trait(?Send)]
+impl DeliveryChannel for TemplateMessage {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Template
+    }
+
+    async fn deliver(
+        &self,
+        state: &web::Data<AppState>,
+        message: &NewMessage,
+    ) -> Result<Push, DeliveryError> {
+        let token_manager = token_manager(state, &self.account)?;
+        let call = apis::send_template_message(token_manager, message);
+        Ok(quota::push(state, &self.account, Api::TemplateSend, call).await?)
+    }
+}
+
+/// Customer service messages of an official account.
+pub struct CustomMessage {
+    pub account: String,
+}
+
+#[async_trait(?Send)]
+impl DeliveryChannel for CustomMessage {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Custom
+    }
+
+    async fn deliver(
+        &self,
+        state: &web::Data<AppState>,
+        message: &NewMessage,
+    ) -> Result<Push, DeliveryError> {
+        let token_manager = token_manager(state, &self.account)?;
+        let call = custom_message::send_text_message(token_manager, message);
+        Ok(quota::push(state, &self.account, Api::CustomSend, call).await?)
+    }
+}
+
+/// Application messages of a WeCom app to a user of the corp.
+pub struct Wecom {
+    pub app: String,
+    pub user_id: String,
+}
+
+#[async_trait(?Send)]
+impl DeliveryChannel for Wecom {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Wecom
+    }
+
+    async fn deliver(
+        &self,
+        state: &web::Data<AppState>,
+        message: &NewMessage,
+    ) -> Result<Push, DeliveryError> {
+        let app = state.config.wecom_app(&self.app).ok_or_else(|| {
+            DeliveryError::Misconfigured(format!("Unknown WeCom app {}", self.app))
+        })?;
+        let token_manager = token_manager(state, &self.app)?;
+        let message = NewMessage {
+            receiver: self.user_id.clone(),
+            ..message.clone()
+        };
+        let call = wecom::send_message(token_manager, app.agent_id, app.message_type, &message);
+        Ok(quota::push(state, &self.app, Api::WecomMessageSend, call).await?)
+    }
+}
+
+/// POST of the message as json to a url of the receiver, signed like outgoing webhooks.
+pub struct Webhook {
+    pub url: String,
+}
+
+#[async_trait(?Send)]
+impl DeliveryChannel for Webhook {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Webhook
+    }
+
+    async fn deliver(
+        &self,
+        state: &web::Data<AppState>,
+        message: &NewMessage,
+    ) -> Result<Push, DeliveryError> {
+        let payload = json!({
+            "token": message.id,
+            "receiver": message.receiver,
+            "title": message.title,
+            "body": message.body,
+            "url": message.url,
+            "detail_url": message.detail_url,
+            "time": unix_timestamp(),
+        })
+        .to_string();
+        let sendkey = crate::sendkey::sendkey(&state.config.secret_key, &message.receiver);
+        let timeout = Duration::from_secs(state.config.webhook.timeout_seconds);
+        let status = crate::webhook::post(&self.url, &sendkey, &payload, timeout).await?;
+        match status.is_success() {
+            true => Ok(Push::Sent(json!({}))),
+            false => Err(DeliveryError::Status(status.as_u16())),
+        }
+    }
+}
+
+/// Email to the verified address of the receiver.
+pub struct Email {
+    pub address: lettre::Address,
+}
+
+#[async_trait(?Send)]
+impl DeliveryChannel for Email {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Email
+    }
+
+    async fn deliver(
+        &self,
+        state: &web::Data<AppState>,
+        message: &NewMessage,
+    ) -> Result<Push, DeliveryError> {
+        // markdown and html bodies are read on the detail page
+        let text = match message.format.unwrap_or_default() {
+            BodyFormat::Text => message.body.as_ref(),
+            _ => message.summary.as_ref(),
+        };
+        let mut body = text.cloned().unwrap_or_default();
+        if let Some(url) = &message.detail_url {
+            body.push_str(&format!("\n\n查看详情：{}", url));
+        }
+        email::send(state, &self.address, &message.title, body).await?;
+        Ok(Push::Sent(json!({})))
+    }
+}
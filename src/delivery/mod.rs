@@ -0,0 +1,411 @@
+//! Delivery of messages to receivers by pluggable channels.
+//!
+//! Each receiver has an ordered fallback chain of channels, by default the
+//! template message of the account they subscribed to, or the WeCom app they
+//! are mapped to. A message is tried on each channel in turn until one takes
+//! it, e.g. when wechat rejects it as the user blocked the account or the
+//! quota is exhausted. The channel taking it is recorded on the message.
+//!
+//! A receiver with a verified email address is emailed as the last resort,
+//! or also when another channel took the message if they opted in to both.
+use actix_web::web;
+use async_trait::async_trait;
+use serde_json::Value;
+
+use crate::config::DEFAULT_ACCOUNT;
+use crate::email::{self, EmailError};
+use crate::errors::Result;
+use crate::models;
+use crate::quota::Push;
+use crate::shared_state::AppState;
+use crate::webhook::WebhookError;
+use crate::wechat::errors::WechatError;
+use crate::wechat::template_message::NewMessage;
+
+pub mod actions;
+mod channels;
+
+pub use channels::{CustomMessage, Email, TemplateMessage, Webhook, Wecom};
+
+/// at most this many channels in a chain
+pub const MAX_CHANNELS: usize = 5;
+
+/// Kinds of delivery channels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelKind {
+    /// template message of an official account
+    Template,
+    /// customer service message of an official account
+    Custom,
+    /// application message of a WeCom app
+    Wecom,
+    /// POST to a url of the receiver
+    Webhook,
+    /// email to the verified address of the receiver
+    Email,
+}
+
+impl ChannelKind {
+    pub const ALL: [ChannelKind; 5] = [
+        ChannelKind::Template,
+        ChannelKind::Custom,
+        ChannelKind::Wecom,
+        ChannelKind::Webhook,
+        ChannelKind::Email,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChannelKind::Template => "template",
+            ChannelKind::Custom => "custom",
+            ChannelKind::Wecom => "wecom",
+            ChannelKind::Webhook => "webhook",
+            ChannelKind::Email => "email",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        ChannelKind::ALL.iter().copied().find(|k| k.as_str() == s)
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum DeliveryError {
+    #[fail(display = "{}", _0)]
+    Wechat(#[fail(cause)] WechatError),
+
+    #[fail(display = "Request failed: {}", _0)]
+    Http(#[fail(cause)] reqwest::Error),
+
+    #[fail(display = "Responded with status {}", _0)]
+    Status(u16),
+
+    #[fail(display = "{}", _0)]
+    Email(#[fail(cause)] EmailError),
+
+    /// the channel can not deliver the message, e.g. with an unknown account
+    #[fail(display = "{}", _0)]
+    Misconfigured(String),
+}
+
+impl From<WechatError> for DeliveryError {
+    fn from(e: WechatError) -> Self {
+        DeliveryError::Wechat(e)
+    }
+}
+
+impl From<EmailError> for DeliveryError {
+    fn from(e: EmailError) -> Self {
+        DeliveryError::Email(e)
+    }
+}
+
+impl From<reqwest::Error> for DeliveryError {
+    fn from(e: reqwest::Error) -> Self {
+        DeliveryError::Http(e)
+    }
+}
+
+impl From<WebhookError> for DeliveryError {
+    fn from(e: WebhookError) -> Self {
+        match e {
+            WebhookError::Http(e) => DeliveryError::Http(e),
+            e => DeliveryError::Misconfigured(e.to_string()),
+        }
+    }
+}
+
+/// A way to deliver messages to a receiver.
+#[async_trait(?Send)]
+pub trait DeliveryChannel {
+    fn kind(&self) -> ChannelKind;
+
+    /// Deliver the message, or queue it if the channel is out of quota.
+    async fn deliver(
+        &self,
+        state: &web::Data<AppState>,
+        message: &NewMessage,
+    ) -> std::result::Result<Push, DeliveryError>;
+}
+
+/// Result of delivering a message by the fallback chain.
+pub enum Delivery {
+    /// taken by the channel, with its response
+    Sent {
+        channel: ChannelKind,
+        response: Value,
+    },
+    /// not taken by any channel, but queued by some to be pushed later
+    Queued,
+}
+
+/// The default channel of the receiver of the account or WeCom app.
+fn default_channel(state: &AppState, account: &str, receiver: &str) -> Box<dyn DeliveryChannel> {
+    match state.config.wecom_app(account) {
+        Some(_) => Box::new(Wecom {
+            app: account.to_owned(),
+            user_id: receiver.to_owned(),
+        }),
+        None => Box::new(TemplateMessage {
+            account: account.to_owned(),
+        }),
+    }
+}
+
+/// The channel configured by the receiver, None if the kind is no longer supported
+/// or the receiver has no verified email address for email.
+fn channel(
+    config: models::DeliveryChannel,
+    account: &str,
+    email: Option<&Email>,
+) -> Option<Box<dyn DeliveryChannel>> {
+    let account = config.account.unwrap_or_else(|| account.to_owned());
+    let channel: Box<dyn DeliveryChannel> = match ChannelKind::parse(&config.kind)? {
+        ChannelKind::Template => Box::new(TemplateMessage { account }),
+        ChannelKind::Custom => Box::new(CustomMessage { account }),
+        ChannelKind::Wecom => Box::new(Wecom {
+            app: account,
+            user_id: config.target.unwrap_or(config.receiver_id),
+        }),
+        ChannelKind::Webhook => Box::new(Webhook {
+            url: config.target.unwrap_or_default(),
+        }),
+        ChannelKind::Email => Box::new(Email {
+            address: email?.address.clone(),
+        }),
+    };
+    Some(channel)
+}
+
+/// Channels of the receiver to deliver a message by.
+struct Chain {
+    /// tried in order
+    channels: Vec<Box<dyn DeliveryChannel>>,
+    /// also emailed when another channel took the message
+    copy: Option<Email>,
+}
+
+impl Chain {
+    /// The configured channels in order, or the default one if none is configured,
+    /// followed by email unless configured, if the receiver has a usable address.
+    fn new(
+        default: Box<dyn DeliveryChannel>,
+        configured: Vec<models::DeliveryChannel>,
+        account: &str,
+        email: Option<Email>,
+        always: bool,
+    ) -> Self {
+        let mut channels = match configured.is_empty() {
+            true => vec![default],
+            false => configured
+                .into_iter()
+                .filter_map(|c| channel(c, account, email.as_ref()))
+                .collect(),
+        };
+        let has_email = channels.iter().any(|c| c.kind() == ChannelKind::Email);
+        let copy = match (email, has_email) {
+            (Some(email), false) => {
+                let address = email.address.clone();
+                channels.push(Box::new(email));
+                match always {
+                    true => Some(Email { address }),
+                    false => None,
+                }
+            }
+            (Some(email), true) if always => Some(email),
+            _ => None,
+        };
+        Chain { channels, copy }
+    }
+}
+
+/// The fallback chain of the receiver, pushing by the account unless configured otherwise,
+/// and emailing the verified address if no channel takes the message.
+async fn chain(state: &web::Data<AppState>, receiver: &str, account: &str) -> Result<Chain> {
+    let db_state = state.clone();
+    let receiver_id = receiver.to_owned();
+    let (configured, email) = web::block(move || {
+        let con = db_state.db_pool.get()?;
+        let configured = actions::find_channels(&receiver_id, &con)?;
+        let email = actions::find_email(&receiver_id, &con)?;
+        Ok((configured, email))
+    })
+    .await?;
+    // unusable once smtp is disabled
+    let (email, always) = match email {
+        Some(e) if state.config.smtp.enabled() => match email::parse_address(&e.address) {
+            Some(address) => (Some(Email { address }), e.always),
+            None => (None, false),
+        },
+        _ => (None, false),
+    };
+    let default = default_channel(state, account, receiver);
+    Ok(Chain::new(default, configured, account, email, always))
+}
+
+/// The copy to email once the channel took the message, none if it was emailed already.
+fn copy_for(copy: Option<Email>, taken_by: ChannelKind) -> Option<Email> {
+    copy.filter(|_| taken_by != ChannelKind::Email)
+}
+
+/// Email the message taken by another channel to a receiver who opted in to both.
+async fn send_copy(state: &web::Data<AppState>, copy: Email, message: &NewMessage) {
+    if let Err(e) = copy.deliver(state, message).await {
+        log::warn!("Failed to email a copy to {}: {}", message.receiver, e);
+    }
+}
+
+/// Deliver the message to its receiver by the first channel in the chain taking it.
+///
+/// The error of the first channel is returned if none takes it, as that is
+/// the one the receiver prefers.
+pub async fn push(
+    state: &web::Data<AppState>,
+    message: &NewMessage,
+) -> std::result::Result<Delivery, DeliveryError> {
+    let account = message.account.as_deref().unwrap_or(DEFAULT_ACCOUNT);
+    let Chain { channels, copy } = match chain(state, &message.receiver, account).await {
+        Ok(chain) => chain,
+        Err(e) => {
+            log::warn!("Failed to load channels of {}: {}", message.receiver, e);
+            Chain {
+                channels: vec![default_channel(state, account, &message.receiver)],
+                copy: None,
+            }
+        }
+    };
+    let mut queued = false;
+    let mut first_error = None;
+    for channel in channels {
+        let kind = channel.kind();
+        match channel.deliver(state, message).await {
+            Ok(Push::Sent(response)) => {
+                if let Some(copy) = copy_for(copy, kind) {
+                    send_copy(state, copy, message).await;
+                }
+                return Ok(Delivery::Sent {
+                    channel: kind,
+                    response,
+                });
+            }
+            Ok(Push::Queued) => queued = true,
+            Err(e) => {
+                log::warn!(
+                    "Failed to deliver to {} by {}: {}",
+                    message.receiver,
+                    kind.as_str(),
+                    e
+                );
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    match (queued, first_error) {
+        (true, _) => Ok(Delivery::Queued),
+        (false, Some(e)) => Err(e),
+        (false, None) => Err(DeliveryError::Misconfigured(
+            "No delivery channel".to_owned(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn configured(kind: &str, target: Option<&str>) -> models::DeliveryChannel {
+        models::DeliveryChannel {
+            receiver_id: "open_id".to_owned(),
+            position: 0,
+            kind: kind.to_owned(),
+            account: None,
+            target: target.map(str::to_owned),
+        }
+    }
+
+    fn email() -> Option<Email> {
+        Some(Email {
+            address: email::parse_address("someone@example.com").unwrap(),
+        })
+    }
+
+    fn default() -> Box<dyn DeliveryChannel> {
+        Box::new(TemplateMessage {
+            account: DEFAULT_ACCOUNT.to_owned(),
+        })
+    }
+
+    fn kinds(chain: &Chain) -> Vec<ChannelKind> {
+        chain.channels.iter().map(|c| c.kind()).collect()
+    }
+
+    #[test]
+    fn test_chain_default() {
+        let chain = Chain::new(default(), vec![], DEFAULT_ACCOUNT, None, false);
+        assert_eq!(kinds(&chain), vec![ChannelKind::Template]);
+        assert!(chain.copy.is_none());
+    }
+
+    #[test]
+    fn test_chain_configured_order() {
+        let channels = vec![
+            configured("webhook", Some("https://example.com/hook")),
+            configured("fax", None),
+            configured("custom", None),
+            configured("template", None),
+        ];
+        let chain = Chain::new(default(), channels, DEFAULT_ACCOUNT, None, false);
+        assert_eq!(
+            kinds(&chain),
+            vec![
+                ChannelKind::Webhook,
+                ChannelKind::Custom,
+                ChannelKind::Template
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chain_email() {
+        // the last resort
+        let chain = Chain::new(default(), vec![], DEFAULT_ACCOUNT, email(), false);
+        assert_eq!(
+            kinds(&chain),
+            vec![ChannelKind::Template, ChannelKind::Email]
+        );
+        assert!(chain.copy.is_none());
+        // also a copy
+        let chain = Chain::new(default(), vec![], DEFAULT_ACCOUNT, email(), true);
+        assert_eq!(
+            kinds(&chain),
+            vec![ChannelKind::Template, ChannelKind::Email]
+        );
+        assert!(chain.copy.is_some());
+        // where configured
+        let channels = vec![configured("email", None), configured("custom", None)];
+        let chain = Chain::new(default(), channels, DEFAULT_ACCOUNT, email(), false);
+        assert_eq!(kinds(&chain), vec![ChannelKind::Email, ChannelKind::Custom]);
+        assert!(chain.copy.is_none());
+        // skipped without a verified address
+        let channels = vec![configured("email", None), configured("custom", None)];
+        let chain = Chain::new(default(), channels, DEFAULT_ACCOUNT, None, true);
+        assert_eq!(kinds(&chain), vec![ChannelKind::Custom]);
+        assert!(chain.copy.is_none());
+    }
+
+    #[test]
+    fn test_copy_for() {
+        assert!(copy_for(email(), ChannelKind::Template).is_some());
+        assert!(copy_for(email(), ChannelKind::Webhook).is_some());
+        assert!(copy_for(email(), ChannelKind::Email).is_none());
+        assert!(copy_for(None, ChannelKind::Template).is_none());
+    }
+
+    #[test]
+    fn test_channel_kind() {
+        for kind in ChannelKind::ALL.iter() {
+            assert_eq!(ChannelKind::parse(kind.as_str()), Some(*kind));
+        }
+        assert_eq!(ChannelKind::parse("fax"), None);
+    }
+}
@@ -0,0 +1,281 @@
+//! Emails to receivers by smtp, as a fallback of wechat or a copy of their messages.
+//!
+//! A receiver registers an address by the api or by sending `email <address>`
+//! to the official account, and verifies it with the code emailed to it. The
+//! pending code is kept in redis until it expires; only verified addresses are
+//! saved and emailed messages.
+use actix_threadpool::BlockingError;
+use actix_web::web;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Address, Message, SmtpTransport, Transport};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::config::SmtpConfig;
+use crate::errors::{Error, Result};
+use crate::models::ReceiverEmail;
+use crate::rate_limit::{self, Bucket};
+use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+
+/// wrong codes tried before the pending verification is dropped
+const MAX_ATTEMPTS: u64 = 5;
+
+#[derive(Debug, Fail)]
+pub enum EmailError {
+    #[fail(display = "Email is not enabled")]
+    Disabled,
+
+    #[fail(display = "Invalid smtp config: {}", _0)]
+    Misconfigured(String),
+
+    #[fail(display = "Error building email")]
+    Message(#[fail(cause)] lettre::error::Error),
+
+    #[fail(display = "Error sending email")]
+    Smtp(#[fail(cause)] lettre::transport::smtp::Error),
+
+    #[fail(display = "Block cancelled")]
+    CancelledBlock,
+}
+
+impl From<lettre::error::Error> for EmailError {
+    fn from(e: lettre::error::Error) -> Self {
+        EmailError::Message(e)
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for EmailError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        EmailError::Smtp(e)
+    }
+}
+
+/// The address if valid, e.g. `someone@example.com` without a display name.
+pub fn parse_address(address: &str) -> Option<Address> {
+    address.trim().parse().ok()
+}
+
+fn transport(config: &SmtpConfig) -> std::result::Result<SmtpTransport, EmailError> {
+    if !config.enabled() {
+        return Err(EmailError::Disabled);
+    }
+    let (builder, port) = match config.tls.as_str() {
+        "tls" => (SmtpTransport::relay(&config.host)?, 465),
+        "starttls" => (SmtpTransport::starttls_relay(&config.host)?, 587),
+        "none" => (SmtpTransport::builder_dangerous(&config.host), 25),
+        tls => return Err(EmailError::Misconfigured(format!("unknown tls {}", tls))),
+    };
+    let mut builder = builder
+        .port(config.port.unwrap_or(port))
+        .timeout(Some(Duration::from_secs(config.timeout_seconds)));
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+    Ok(builder.build())
+}
+
+fn send_blocking(
+    config: &SmtpConfig,
+    to: &Address,
+    subject: &str,
+    body: String,
+) -> std::result::Result<(), EmailError> {
+    let transport = transport(config)?;
+    let from: Mailbox = config
+        .from
+        .parse()
+        .map_err(|_| EmailError::Misconfigured(format!("invalid from {}", config.from)))?;
+    let email = Message::builder()
+        .from(from)
+        .to(Mailbox::new(None, to.clone()))
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(body)?;
+    transport.send(&email)?;
+    Ok(())
+}
+
+/// Send a plain text email.
+pub async fn send(
+    state: &web::Data<AppState>,
+    to: &Address,
+    subject: &str,
+    body: String,
+) -> std::result::Result<(), EmailError> {
+    let state = state.clone();
+    let to = to.clone();
+    let subject = subject.to_owned();
+    web::block(move || send_blocking(&state.config.smtp, &to, &subject, body))
+        .await
+        .map_err(|e| match e {
+            BlockingError::Error(e) => e,
+            BlockingError::Canceled => EmailError::CancelledBlock,
+        })
+}
+
+/// An address waiting for its code to be verified.
+#[derive(Serialize, Deserialize)]
+struct Pending {
+    address: String,
+    always: bool,
+    code: String,
+}
+
+fn pending_key(receiver: &str) -> String {
+    format!("wxpush:email:pending:{}", receiver)
+}
+
+fn attempts_key(receiver: &str) -> String {
+    format!("wxpush:email:attempts:{}", receiver)
+}
+
+/// Email a code to the address, to be verified by `verify`.
+pub async fn request_verification(
+    state: &web::Data<AppState>,
+    receiver: &str,
+    address: &str,
+    always: bool,
+) -> Result<Address> {
+    let smtp = &state.config.smtp;
+    if !smtp.enabled() {
+        return Err(Error::BadRequest("Email is not enabled".to_owned()));
+    }
+    let address = parse_address(address)
+        .ok_or_else(|| Error::BadRequest(format!("Invalid email address {}", address)))?;
+    // codes can not be used to flood a receiver or an address
+    rate_limit::check(state, Bucket::EmailReceiver, receiver).await?;
+    let address_id = address.to_string().to_lowercase();
+    rate_limit::check(state, Bucket::EmailAddress, &address_id).await?;
+    let pending = Pending {
+        address: address.to_string(),
+        always,
+        code: format!("{:06}", rand::random::<u32>() % 1_000_000),
+    };
+    let body = format!(
+        "验证码：{}\n\n{} 分钟内有效，验证后消息也将发送至此邮箱。",
+        pending.code, smtp.code_minutes
+    );
+
+    let ttl = (smtp.code_minutes * 60) as usize;
+    let value = serde_json::to_string(&pending).unwrap();
+    let mut con = state.redis_connection().await?;
+    let _: () = con.set_ex(pending_key(receiver), value, ttl).await?;
+    let _: () = con.del(attempts_key(receiver)).await?;
+    send(state, &address, "验证邮箱", body).await?;
+    Ok(address)
+}
+
+/// Save the pending address of the receiver if the code matches.
+pub async fn verify(
+    state: &web::Data<AppState>,
+    receiver: &str,
+    code: &str,
+) -> Result<ReceiverEmail> {
+    let mut con = state.redis_connection().await?;
+    let value: Option<String> = con.get(pending_key(receiver)).await?;
+    let pending: Pending = match value.and_then(|v| serde_json::from_str(&v).ok()) {
+        Some(pending) => pending,
+        None => return Err(Error::NotFound("No pending verification".to_owned())),
+    };
+    if pending.code != code.trim() {
+        let attempts: u64 = con.incr(attempts_key(receiver), 1).await?;
+        let _: () = con
+            .expire(
+                attempts_key(receiver),
+                (state.config.smtp.code_minutes * 60) as usize,
+            )
+            .await?;
+        if attempts >= MAX_ATTEMPTS {
+            let _: () = con
+                .del(&[pending_key(receiver), attempts_key(receiver)])
+                .await?;
+            return Err(Error::BadRequest(
+                "Too many wrong codes, register the address again".to_owned(),
+            ));
+        }
+        return Err(Error::BadRequest("Wrong verification code".to_owned()));
+    }
+    let _: () = con
+        .del(&[pending_key(receiver), attempts_key(receiver)])
+        .await?;
+
+    let email = ReceiverEmail {
+        receiver_id: receiver.to_owned(),
+        address: pending.address,
+        always: pending.always,
+        verified_time: unix_timestamp(),
+    };
+    let db_state = state.clone();
+    let saved = email.clone();
+    web::block(move || {
+        let con = db_state.db_pool.get()?;
+        crate::delivery::actions::save_email(&saved, &con)
+    })
+    .await?;
+    Ok(email)
+}
+
+/// Commands sent to the official account as text messages.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// `email`
+    Show,
+    /// `email <address> [always]`
+    Register { address: String, always: bool },
+    /// `email <code>`
+    Verify(String),
+    /// `email off`
+    Remove,
+}
+
+/// Parse `email ...` or `邮箱 ...`, None for other text.
+pub fn parse_command(text: &str) -> Option<Command> {
+    let mut words = text.split_whitespace();
+    match words.next()? {
+        w if w.eq_ignore_ascii_case("email") || w == "邮箱" => {}
+        _ => return None,
+    }
+    let command = match words.next() {
+        None => Command::Show,
+        Some(w) if w.eq_ignore_ascii_case("off") => Command::Remove,
+        Some(w) if w.len() == 6 && w.chars().all(|c| c.is_ascii_digit()) => {
+            Command::Verify(w.to_owned())
+        }
+        Some(w) => Command::Register {
+            address: w.to_owned(),
+            always: words
+                .next()
+                .map_or(false, |w| w.eq_ignore_ascii_case("always")),
+        },
+    };
+    Some(command)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("hello"), None);
+        assert_eq!(parse_command("Email"), Some(Command::Show));
+        assert_eq!(parse_command("email off"), Some(Command::Remove));
+        assert_eq!(
+            parse_command("邮箱 012345"),
+            Some(Command::Verify("012345".to_owned()))
+        );
+        assert_eq!(
+            parse_command(" email a@example.com always"),
+            Some(Command::Register {
+                address: "a@example.com".to_owned(),
+                always: true
+            })
+        );
+        assert!(parse_address("a@example.com").is_some());
+        assert!(parse_address("not an address").is_none());
+    }
+}
@@ -6,6 +6,8 @@ use failure::Context;
 use r2d2::Error as R2D2Error;
 use redis::RedisError;
 
+use crate::delivery::DeliveryError;
+use crate::email::EmailError;
 use crate::wechat::errors::WechatError;
 
 #[derive(Debug, Fail)]
@@ -28,6 +30,12 @@ pub enum InternalError {
     // wechat
     #[fail(display = "Error calling wechat api")]
     Wechat(#[fail(cause)] WechatError),
+
+    #[fail(display = "Error delivering message")]
+    Delivery(#[fail(cause)] DeliveryError),
+
+    #[fail(display = "Error sending email")]
+    Email(#[fail(cause)] EmailError),
 }
 
 /// Error type for all Result for app handlers.
@@ -58,6 +66,13 @@ pub enum Error {
 
     #[fail(display = "Not found: {}", _0)]
     NotFound(String),
+
+    #[fail(display = "Conflict: {}", _0)]
+    Conflict(String),
+
+    /// rate limited, with the seconds to retry after
+    #[fail(display = "Too many requests, retry after {} seconds", _0)]
+    TooManyRequests(u64),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -69,6 +84,8 @@ impl ResponseError for Error {
             Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
             Self::BadRequest(_) => StatusCode::BAD_REQUEST,
             Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
         }
     }
     fn error_response(&self) -> HttpResponse {
@@ -78,6 +95,12 @@ impl ResponseError for Error {
             Self::Unauthorized(_) => HttpResponse::Unauthorized(),
             Self::BadRequest(_) => HttpResponse::BadRequest(),
             Self::NotFound(_) => HttpResponse::NotFound(),
+            Self::Conflict(_) => HttpResponse::Conflict(),
+            Self::TooManyRequests(retry_after) => {
+                let mut builder = HttpResponse::TooManyRequests();
+                builder.header("Retry-After", retry_after.to_string());
+                builder
+            }
         };
         response_builder.json::<ErrorResponse>(self.into())
     }
@@ -104,6 +127,16 @@ impl From<WechatError> for Error {
         Error::InternalError(InternalError::Wechat(e))
     }
 }
+impl From<DeliveryError> for Error {
+    fn from(e: DeliveryError) -> Self {
+        Error::InternalError(InternalError::Delivery(e))
+    }
+}
+impl From<EmailError> for Error {
+    fn from(e: EmailError) -> Self {
+        Error::InternalError(InternalError::Email(e))
+    }
+}
 // block
 impl From<BlockingError<Error>> for Error {
     fn from(e: BlockingError<Error>) -> Self {
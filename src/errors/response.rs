@@ -12,12 +12,14 @@ impl From<&Error> for ErrorResponse {
         use Error::*;
         let errmsg = match e {
             InternalError(_) | OtherInternal(_) => "Internal Error".to_owned(),
-            Unauthorized(s) | BadRequest(s) | NotFound(s) => s.clone(),
+            Unauthorized(s) | BadRequest(s) | NotFound(s) | Conflict(s) => s.clone(),
+            TooManyRequests(_) => "Too many requests".to_owned(),
         };
         let detail = match e {
             InternalError(e) => Some(format!("{}", e)),
             OtherInternal(e) => Some(format!("{}", e)),
-            Unauthorized(_) | BadRequest(_) | NotFound(_) => None,
+            TooManyRequests(retry_after) => Some(format!("Retry after {} seconds", retry_after)),
+            Unauthorized(_) | BadRequest(_) | NotFound(_) | Conflict(_) => None,
         };
         Self { errmsg, detail }
     }
@@ -0,0 +1,142 @@
+use pulldown_cmark::{html, Event, Options, Parser, Tag};
+use serde::{Deserialize, Serialize};
+
+/// Format of the message body.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyFormat {
+    #[default]
+    Text,
+    Markdown,
+    Html,
+}
+
+impl BodyFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BodyFormat::Text => "text",
+            BodyFormat::Markdown => "markdown",
+            BodyFormat::Html => "html",
+        }
+    }
+
+    /// Parse the format saved in database, unknown values are taken as text.
+    pub fn from_str_lossy(s: &str) -> Self {
+        match s {
+            "markdown" => BodyFormat::Markdown,
+            "html" => BodyFormat::Html,
+            _ => BodyFormat::Text,
+        }
+    }
+}
+
+/// Max chars of the summary pushed in the template message.
+pub const SUMMARY_MAX_CHARS: usize = 200;
+
+fn markdown_parser(body: &str) -> Parser<'_> {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    Parser::new_ext(body, options)
+}
+
+/// Render the body as sanitised html.
+pub fn render_html(body: &str, format: BodyFormat) -> String {
+    match format {
+        BodyFormat::Text => super::escape(body),
+        BodyFormat::Markdown => {
+            let mut unsafe_html = String::new();
+            html::push_html(&mut unsafe_html, markdown_parser(body));
+            ammonia::clean(&unsafe_html)
+        }
+        BodyFormat::Html => ammonia::clean(body),
+    }
+}
+
+fn markdown_to_text(body: &str) -> String {
+    let mut text = String::new();
+    for event in markdown_parser(body) {
+        match event {
+            Event::Text(s) | Event::Code(s) => text.push_str(&s),
+            Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::End(Tag::Paragraph)
+            | Event::End(Tag::Heading(_))
+            | Event::End(Tag::Item)
+            | Event::End(Tag::CodeBlock(_))
+            | Event::End(Tag::TableHead)
+            | Event::End(Tag::TableRow) => text.push('\n'),
+            Event::End(Tag::TableCell) => text.push(' '),
+            _ => {}
+        }
+    }
+    text
+}
+
+fn html_to_text(body: &str) -> String {
+    // sanitise first, which also drops the content of <script> and <style>
+    let body = ammonia::clean(body);
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in body.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// Plain text summary of the body with at most `max_chars` chars, e.g. for the template message.
+pub fn summarize(body: &str, format: BodyFormat, max_chars: usize) -> String {
+    let text = match format {
+        BodyFormat::Text => body.to_owned(),
+        BodyFormat::Markdown => markdown_to_text(body),
+        BodyFormat::Html => html_to_text(body),
+    };
+    let text = text.trim();
+    match text.char_indices().nth(max_chars) {
+        None => text.to_owned(),
+        Some((end, _)) => format!("{}…", &text[..end]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_html() {
+        let html = render_html(
+            "# Title\n\n<script>alert(1)</script>\n\n**bold**",
+            BodyFormat::Markdown,
+        );
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(html.contains("<strong>bold</strong>"));
+        assert!(!html.contains("script"));
+
+        let html = render_html(r#"<a href="javascript:alert(1)">x</a>"#, BodyFormat::Html);
+        assert!(!html.contains("javascript"));
+
+        assert_eq!(render_html("<b>", BodyFormat::Text), "&lt;b&gt;");
+    }
+
+    #[test]
+    fn test_summarize() {
+        let markdown = "# Disk\n\n| host | usage |\n|---|---|\n| db1 | `95%` |";
+        assert_eq!(
+            summarize(markdown, BodyFormat::Markdown, 100),
+            "Disk\nhost usage \ndb1 95%"
+        );
+        assert_eq!(
+            summarize("<p>a &amp; b</p><script>x</script>", BodyFormat::Html, 100),
+            "a & b"
+        );
+        assert_eq!(summarize("abcdef", BodyFormat::Text, 3), "abc…");
+    }
+}
@@ -0,0 +1,108 @@
+//! Minimal html templates built into the binary.
+//!
+//! Templates contain `{{name}}` placeholders which are substituted with
+//! escaped text by `Template::text`, or with trusted html by `Template::html`.
+
+pub mod body;
+
+use std::collections::HashMap;
+
+const LAYOUT: &str = include_str!("templates/layout.html");
+
+/// Whether the url is safe to put in a link, i.e. not `javascript:` and alike.
+pub fn is_safe_url(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Escape text for use in html content and attribute values.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+pub struct Template {
+    template: String,
+    values: HashMap<String, String>,
+}
+
+impl Template {
+    pub fn new(template: &str) -> Self {
+        Template {
+            template: template.to_owned(),
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn text(self, key: &str, value: &str) -> Self {
+        self.html(key, &escape(value))
+    }
+
+    pub fn html(mut self, key: &str, html: &str) -> Self {
+        self.values.insert(key.to_owned(), html.to_owned());
+        self
+    }
+
+    /// Substitute the placeholders in a single pass over the template, so that
+    /// placeholders in the values are left as they are.
+    pub fn render(self) -> String {
+        let mut rendered = String::with_capacity(self.template.len());
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find("{{") {
+            let end = match rest[start..].find("}}") {
+                Some(end) => start + end,
+                None => break,
+            };
+            match self.values.get(&rest[start + 2..end]) {
+                Some(value) => {
+                    rendered.push_str(&rest[..start]);
+                    rendered.push_str(value);
+                }
+                None => rendered.push_str(&rest[..end + 2]),
+            }
+            rest = &rest[end + 2..];
+        }
+        rendered.push_str(rest);
+        rendered
+    }
+
+    /// Render as a full page in the shared layout.
+    pub fn page(self, title: &str) -> String {
+        Template::new(LAYOUT)
+            .text("title", title)
+            .html("content", &self.render())
+            .render()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_template() {
+        let s = Template::new("<h1>{{title}}</h1>{{body}}")
+            .text("title", "<script>\"x\"</script>")
+            .html("body", "<p>ok</p>")
+            .render();
+        assert_eq!(
+            s,
+            "<h1>&lt;script&gt;&quot;x&quot;&lt;/script&gt;</h1><p>ok</p>"
+        );
+        let s = Template::new("{{a}}|{{b}}|{{c}}")
+            .text("a", "{{b}}")
+            .text("b", "x")
+            .render();
+        assert_eq!(s, "{{b}}|x|{{c}}");
+    }
+}
@@ -0,0 +1,84 @@
+use actix_web::web;
+use std::time::Duration;
+
+use crate::delivery::{self, Delivery};
+use crate::errors::Result;
+use crate::html::body::{summarize, BodyFormat, SUMMARY_MAX_CHARS};
+use crate::models::Message;
+use crate::routes::message::{actions, detail_url};
+use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+use crate::wechat::template_message::NewMessage;
+
+/// Periodically close dedup windows, pushing a follow-up for messages with duplicates.
+pub async fn run(state: web::Data<AppState>) {
+    let config = &state.config.dedup;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.poll_seconds));
+    loop {
+        interval.tick().await;
+        if let Err(e) = poll(&state).await {
+            log::error!("Failed to close dedup windows: {}", e);
+        }
+    }
+}
+
+async fn poll(state: &web::Data<AppState>) -> Result<()> {
+    let batch_size = state.config.dedup.batch_size;
+    loop {
+        let db_state = state.clone();
+        let closed = web::block(move || {
+            let con = db_state.db_pool.get()?;
+            actions::close_dedup_windows(unix_timestamp(), batch_size, &con)
+        })
+        .await?;
+        let count = closed.len() as i64;
+        for message in closed {
+            follow_up(state, message).await;
+        }
+        if count < batch_size {
+            return Ok(());
+        }
+    }
+}
+
+/// Push the count of duplicates, linking to the first message.
+async fn follow_up(state: &web::Data<AppState>, message: Message) {
+    let now = unix_timestamp();
+    let gone = message.revoked_time.is_some()
+        || message.acked_time.is_some()
+        || message.expires_at.map_or(false, |t| t <= now);
+    if message.duplicates == 0 || gone {
+        return;
+    }
+    let minutes = (message.dedup_until.unwrap_or(now) - message.created_time) / 60;
+    let format = BodyFormat::from_str_lossy(&message.format);
+    let account = state.config.account_by_app_id(&message.app_id);
+    let follow_up = NewMessage {
+        title: format!(
+            "{} (x{} in last {} min)",
+            message.title,
+            message.duplicates + 1,
+            minutes
+        ),
+        summary: Some(summarize(&message.body, format, SUMMARY_MAX_CHARS)),
+        template_id: Some(message.template_id.clone()),
+        detail_url: Some(detail_url(
+            &state.config,
+            account,
+            &message.id,
+            &message.receiver_id,
+        )),
+        account: Some(account.to_owned()),
+        id: Some(message.id),
+        receiver: message.receiver_id,
+        ..NewMessage::default()
+    };
+    // a follow-up is best effort, not retried
+    match delivery::push(state, &follow_up).await {
+        Ok(Delivery::Sent { .. }) => log::info!("Follow-up of message {} pushed", message.id),
+        Ok(Delivery::Queued) => {
+            log::warn!("Follow-up of message {} dropped, quota reached", message.id)
+        }
+        Err(e) => log::warn!("Failed to push follow-up of message {}: {}", message.id, e),
+    }
+}
@@ -0,0 +1,239 @@
+use actix_web::web;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::delivery::{self, Delivery};
+use crate::errors::Result;
+use crate::html::body::{summarize, BodyFormat, SUMMARY_MAX_CHARS};
+use crate::models::{Digest, Message};
+use crate::routes::message::{actions, detail_url, digest_url};
+use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+use crate::webhook::{self, Event};
+use crate::wechat::template_message::NewMessage;
+use serde_json::json;
+
+/// Claimed messages are not picked up again within this time, even if the poller died.
+const LEASE_SECONDS: i64 = 5 * 60;
+
+/// Periodically push messages held in quiet hours or for digest mode, one digest per receiver.
+pub async fn run(state: web::Data<AppState>) {
+    let config = &state.config.digest;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.poll_seconds));
+    loop {
+        interval.tick().await;
+        if let Err(e) = poll(&state).await {
+            log::error!("Failed to push digests: {}", e);
+        }
+    }
+}
+
+async fn poll(state: &web::Data<AppState>) -> Result<()> {
+    let batch_size = state.config.digest.batch_size;
+    loop {
+        let db_state = state.clone();
+        let claimed = web::block(move || {
+            let con = db_state.db_pool.get()?;
+            actions::claim_held_messages(unix_timestamp(), LEASE_SECONDS, batch_size, &con)
+        })
+        .await?;
+        let count = claimed.len() as i64;
+        for group in group_by_receiver(claimed) {
+            // picked up again when the lease expires
+            let receiver = group[0].receiver_id.clone();
+            if let Err(e) = push(state, group).await {
+                log::error!("Failed to push held messages to {}: {}", receiver, e);
+            }
+        }
+        if count < batch_size {
+            return Ok(());
+        }
+    }
+}
+
+/// Group messages ordered by receiver and app id, so that each digest is pushed by the
+/// account its messages were sent to.
+fn group_by_receiver(messages: Vec<Message>) -> Vec<Vec<Message>> {
+    let mut groups: Vec<Vec<Message>> = Vec::new();
+    for message in messages {
+        match groups.last_mut() {
+            Some(group)
+                if group[0].receiver_id == message.receiver_id
+                    && group[0].app_id == message.app_id =>
+            {
+                group.push(message)
+            }
+            _ => groups.push(vec![message]),
+        }
+    }
+    groups
+}
+
+/// Push the held messages of a receiver by an account, in a digest if there are more than one.
+///
+/// Messages queued as the quota is reached stay held, and are retried once the lease expires.
+async fn push(state: &web::Data<AppState>, messages: Vec<Message>) -> Result<()> {
+    let now = unix_timestamp();
+    let (live, gone): (Vec<Message>, Vec<Message>) = messages.into_iter().partition(|m| {
+        m.revoked_time.is_none() && m.acked_time.is_none() && m.expires_at.map_or(true, |t| t > now)
+    });
+    if !gone.is_empty() {
+        let ids: Vec<Uuid> = gone.iter().map(|m| m.id).collect();
+        let db_state = state.clone();
+        web::block(move || {
+            let con = db_state.db_pool.get()?;
+            actions::release_held_messages(&ids, None, None, &con)
+        })
+        .await?;
+    }
+    if live.is_empty() {
+        return Ok(());
+    }
+
+    let receiver = live[0].receiver_id.clone();
+    let account = state.config.account_by_app_id(&live[0].app_id);
+    let digest_id = Uuid::new_v4();
+    let new_message = match &live[..] {
+        [message] => {
+            let format = BodyFormat::from_str_lossy(&message.format);
+            NewMessage {
+                title: message.title.clone(),
+                summary: Some(summarize(&message.body, format, SUMMARY_MAX_CHARS)),
+                template_id: Some(message.template_id.clone()),
+                detail_url: Some(detail_url(&state.config, account, &message.id, &receiver)),
+                account: Some(account.to_owned()),
+                id: Some(message.id),
+                receiver: receiver.clone(),
+                ..NewMessage::default()
+            }
+        }
+        _ => {
+            let titles: Vec<&str> = live.iter().map(|m| m.title.as_str()).collect();
+            NewMessage {
+                title: format!("{} new messages", live.len()),
+                summary: Some(summarize(
+                    &titles.join("\n"),
+                    BodyFormat::Text,
+                    SUMMARY_MAX_CHARS,
+                )),
+                template_id: Some(
+                    state
+                        .config
+                        .account(account)
+                        .unwrap_or(&state.config.wechat)
+                        .default_template_id
+                        .clone(),
+                ),
+                detail_url: Some(digest_url(&state.config, &digest_id)),
+                account: Some(account.to_owned()),
+                id: Some(digest_id),
+                receiver: receiver.clone(),
+                ..NewMessage::default()
+            }
+        }
+    };
+    let (event, data, msg_id, channel) = match delivery::push(state, &new_message).await {
+        Ok(Delivery::Sent { channel, response }) => {
+            log::info!("{} held messages pushed to {}", live.len(), receiver);
+            (
+                Event::Sent,
+                json!({}),
+                response["msgid"].as_i64(),
+                Some(channel),
+            )
+        }
+        Ok(Delivery::Queued) => return Ok(()),
+        // not retried, as for messages pushed right away
+        Err(e) => {
+            log::warn!("Failed to push held messages to {}: {}", receiver, e);
+            (
+                Event::Failed,
+                json!({ "reason": e.to_string() }),
+                None,
+                None,
+            )
+        }
+    };
+
+    let ids: Vec<Uuid> = live.iter().map(|m| m.id).collect();
+    let db_state = state.clone();
+    let saved_ids = ids.clone();
+    let digest = Digest {
+        id: digest_id,
+        receiver_id: receiver.clone(),
+        created_time: now,
+    };
+    web::block(move || {
+        let con = db_state.db_pool.get()?;
+        match saved_ids.len() {
+            1 => actions::release_held_messages(&saved_ids, msg_id, channel, &con),
+            _ => actions::insert_digest(&digest, &saved_ids, channel, &con),
+        }
+    })
+    .await?;
+    for id in ids {
+        webhook::emit(state, event, &receiver, id, data.clone(), None).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(receiver: &str, app_id: &str) -> Message {
+        Message {
+            id: Uuid::new_v4(),
+            app_id: app_id.to_owned(),
+            template_id: String::new(),
+            receiver_id: receiver.to_owned(),
+            title: String::new(),
+            body: String::new(),
+            url: None,
+            created_time: 0,
+            ip: String::new(),
+            UA: String::new(),
+            format: String::new(),
+            expires_at: None,
+            revoked_time: None,
+            acked_time: None,
+            acked_by: None,
+            ack_webhook: None,
+            wechat_msg_id: None,
+            dedup_key: None,
+            dedup_until: None,
+            duplicates: 0,
+            dedup_closed: false,
+            queued: false,
+            priority: String::new(),
+            held_until: None,
+            digest_id: None,
+            channel: None,
+            queue_lease: None,
+            sender_id: None,
+        }
+    }
+
+    #[test]
+    fn test_group_by_receiver() {
+        let messages = vec![
+            message("a", "app1"),
+            message("a", "app1"),
+            message("a", "app2"),
+            message("b", "app1"),
+        ];
+        let groups: Vec<Vec<(String, String)>> = group_by_receiver(messages)
+            .into_iter()
+            .map(|g| g.into_iter().map(|m| (m.receiver_id, m.app_id)).collect())
+            .collect();
+        let pair = |r: &str, a: &str| (r.to_owned(), a.to_owned());
+        assert_eq!(
+            groups,
+            vec![
+                vec![pair("a", "app1"), pair("a", "app1")],
+                vec![pair("a", "app2")],
+                vec![pair("b", "app1")],
+            ]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use actix_web::web;
+use std::time::Duration;
+
+use crate::delivery::{self, Delivery};
+use crate::errors::Result;
+use crate::html::body::{summarize, BodyFormat, SUMMARY_MAX_CHARS};
+use crate::models::{Escalation, Message};
+use crate::routes::message::{actions, detail_url};
+use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+use crate::wechat::template_message::NewMessage;
+
+/// Claimed escalations are not picked up again within this time, even if the poller died.
+const LEASE_SECONDS: i64 = 5 * 60;
+const TITLE_PREFIX: &str = "[Unread] ";
+
+/// Periodically resend messages with due escalations until they are viewed.
+pub async fn run(state: web::Data<AppState>) {
+    let config = &state.config.escalation;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.poll_seconds));
+    loop {
+        interval.tick().await;
+        if let Err(e) = poll(&state).await {
+            log::error!("Failed to escalate messages: {}", e);
+        }
+    }
+}
+
+async fn poll(state: &web::Data<AppState>) -> Result<()> {
+    let batch_size = state.config.escalation.batch_size;
+    loop {
+        let db_state = state.clone();
+        let due = web::block(move || {
+            let con = db_state.db_pool.get()?;
+            actions::claim_due_escalations(unix_timestamp(), LEASE_SECONDS, batch_size, &con)
+        })
+        .await?;
+        let claimed = due.len() as i64;
+        for escalation in due {
+            // picked up again when the lease expires
+            let id = escalation.id;
+            if let Err(e) = escalate(state, escalation).await {
+                log::error!("Failed to escalate {}: {}", id, e);
+            }
+        }
+        if claimed < batch_size {
+            return Ok(());
+        }
+    }
+}
+
+/// The receiver of the next step, the last one is kept for any remaining steps.
+fn next_receiver<'a>(escalation: &'a Escalation, message: &'a Message) -> &'a str {
+    if escalation.step == 0 || escalation.receivers.is_empty() {
+        return &message.receiver_id;
+    }
+    let index = (escalation.step as usize - 1).min(escalation.receivers.len() - 1);
+    &escalation.receivers[index]
+}
+
+async fn escalate(state: &web::Data<AppState>, mut escalation: Escalation) -> Result<()> {
+    let uuid = escalation.message_id;
+    let db_state = state.clone();
+    let (message, views) = web::block(move || {
+        let con = db_state.db_pool.get()?;
+        let message = actions::find_message_by_uuid(uuid, &con)?;
+        let views = actions::count_views(uuid, &con)?;
+        Ok((message, views))
+    })
+    .await?;
+
+    let now = unix_timestamp();
+    match message {
+        Some(ref m)
+            if m.acked_time.is_some()
+                || m.revoked_time.is_some()
+                || m.expires_at.map_or(false, |t| t <= now) =>
+        {
+            escalation.status = "cancelled".to_owned();
+        }
+        None => escalation.status = "cancelled".to_owned(),
+        Some(_) if views > 0 => escalation.status = "seen".to_owned(),
+        Some(_) if escalation.step >= escalation.max_steps => {
+            escalation.status = "exhausted".to_owned();
+        }
+        Some(message) => {
+            let receiver = next_receiver(&escalation, &message).to_owned();
+            let account = state.config.account_by_app_id(&message.app_id);
+            let format = BodyFormat::from_str_lossy(&message.format);
+            let resend = NewMessage {
+                receiver: receiver.clone(),
+                title: format!("{}{}", TITLE_PREFIX, message.title),
+                summary: Some(summarize(&message.body, format, SUMMARY_MAX_CHARS)),
+                template_id: Some(message.template_id.clone()),
+                detail_url: Some(detail_url(&state.config, account, &message.id, &receiver)),
+                account: Some(account.to_owned()),
+                id: Some(message.id),
+                ..NewMessage::default()
+            };
+            // a failed step is not retried, the next one may reach someone else
+            let taken = match delivery::push(state, &resend).await {
+                Ok(Delivery::Sent { .. }) => {
+                    log::info!(
+                        "Message {} escalated to {}, step {}",
+                        message.id,
+                        receiver,
+                        escalation.step + 1
+                    );
+                    true
+                }
+                // retry the step later, hopefully after the quota resets
+                Ok(Delivery::Queued) => false,
+                Err(e) => {
+                    log::warn!("Failed to escalate message {}: {}", message.id, e);
+                    true
+                }
+            };
+            if taken {
+                escalation.step += 1;
+            }
+            escalation.next_time = now + escalation.interval_seconds;
+        }
+    }
+
+    let db_state = state.clone();
+    web::block(move || {
+        let con = db_state.db_pool.get()?;
+        actions::update_escalation(&escalation, &con)
+    })
+    .await?;
+    Ok(())
+}
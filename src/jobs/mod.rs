@@ -0,0 +1,23 @@
+//! Background jobs running alongside the web server.
+use actix_web::web;
+
+use crate::shared_state::AppState;
+
+mod dedup;
+mod digest;
+mod escalation;
+mod queue;
+mod retention;
+mod sendkey;
+mod webhook;
+
+/// Spawn all background jobs on the current arbiter.
+pub fn spawn_all(state: web::Data<AppState>) {
+    actix_rt::spawn(retention::run(state.clone()));
+    actix_rt::spawn(escalation::run(state.clone()));
+    actix_rt::spawn(webhook::run(state.clone()));
+    actix_rt::spawn(dedup::run(state.clone()));
+    actix_rt::spawn(queue::run(state.clone()));
+    actix_rt::spawn(digest::run(state.clone()));
+    actix_rt::spawn(sendkey::run(state));
+}
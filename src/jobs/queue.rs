@@ -0,0 +1,111 @@
+use actix_web::web;
+use std::time::Duration;
+
+use crate::delivery::{self, Delivery};
+use crate::errors::Result;
+use crate::html::body::{summarize, BodyFormat, SUMMARY_MAX_CHARS};
+use crate::models::Message;
+use crate::quota::{self, Api};
+use crate::routes::message::{actions, detail_url};
+use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+use crate::webhook::{self, Event};
+use crate::wechat::template_message::NewMessage;
+use serde_json::json;
+
+/// Claimed messages are not picked up again within this time, even if the job died.
+const LEASE_SECONDS: i64 = 5 * 60;
+
+/// Periodically push messages queued when the quota was reached, of accounts whose default
+/// push api is out of queue-only mode.
+pub async fn run(state: web::Data<AppState>) {
+    let config = &state.config.quota;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.poll_seconds));
+    loop {
+        interval.tick().await;
+        if let Err(e) = poll(&state).await {
+            log::error!("Failed to push queued messages: {}", e);
+        }
+    }
+}
+
+async fn poll(state: &web::Data<AppState>) -> Result<()> {
+    let batch_size = state.config.quota.batch_size;
+    loop {
+        let mut excluded = Vec::new();
+        for name in state.config.account_names() {
+            let api = match state.config.wecom_app(name) {
+                Some(_) => Api::WecomMessageSend,
+                None => Api::TemplateSend,
+            };
+            if quota::is_queue_only(state, name, api).await? {
+                excluded.extend(state.config.app_id(name));
+            }
+        }
+        if excluded.len() == state.token_managers.len() {
+            return Ok(());
+        }
+        let db_state = state.clone();
+        let claimed = web::block(move || {
+            let con = db_state.db_pool.get()?;
+            let now = unix_timestamp();
+            actions::claim_queued_messages(now, LEASE_SECONDS, batch_size, &excluded, &con)
+        })
+        .await?;
+        let count = claimed.len() as i64;
+        for message in claimed {
+            // claimed ones left are pushed when their leases expire
+            if !push(state, message).await? {
+                return Ok(());
+            }
+        }
+        if count < batch_size {
+            return Ok(());
+        }
+    }
+}
+
+/// Push a queued message, returns false if it's queued again.
+async fn push(state: &web::Data<AppState>, message: Message) -> Result<bool> {
+    let uuid = message.id;
+    let format = BodyFormat::from_str_lossy(&message.format);
+    let account = state.config.account_by_app_id(&message.app_id);
+    let new_message = NewMessage {
+        title: message.title.clone(),
+        summary: Some(summarize(&message.body, format, SUMMARY_MAX_CHARS)),
+        template_id: Some(message.template_id.clone()),
+        detail_url: Some(detail_url(
+            &state.config,
+            account,
+            &uuid,
+            &message.receiver_id,
+        )),
+        account: Some(account.to_owned()),
+        id: Some(uuid),
+        receiver: message.receiver_id.clone(),
+        ..NewMessage::default()
+    };
+    let (queued, msg_id, channel) = match delivery::push(state, &new_message).await {
+        Ok(Delivery::Sent { channel, response }) => {
+            log::info!("Queued message {} pushed by {}", uuid, channel.as_str());
+            let data = json!({});
+            webhook::emit(state, Event::Sent, &message.receiver_id, uuid, data, None).await;
+            (false, response["msgid"].as_i64(), Some(channel))
+        }
+        Ok(Delivery::Queued) => (true, None, None),
+        // not retried, as for messages pushed right away
+        Err(e) => {
+            log::warn!("Failed to push queued message {}: {}", uuid, e);
+            let data = json!({ "reason": e.to_string() });
+            webhook::emit(state, Event::Failed, &message.receiver_id, uuid, data, None).await;
+            (false, None, None)
+        }
+    };
+    let db_state = state.clone();
+    web::block(move || {
+        let con = db_state.db_pool.get()?;
+        actions::update_queued_message(uuid, queued, msg_id, channel, &con)
+    })
+    .await?;
+    Ok(!queued)
+}
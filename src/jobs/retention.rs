@@ -0,0 +1,123 @@
+use actix_web::web;
+use redis::AsyncCommands;
+use std::time::Duration;
+
+use crate::config::{RetentionConfig, RetentionRule};
+use crate::errors::Result;
+use crate::routes::message::actions::{self, SenderScope};
+use crate::routes::message::redis_key;
+use crate::routes::webhook;
+use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Periodically delete or anonymise messages past retention.
+pub async fn run(state: web::Data<AppState>) {
+    let config = &state.config.retention;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_seconds));
+    loop {
+        interval.tick().await;
+        if let Err(e) = purge(&state).await {
+            log::error!("Failed to purge messages: {}", e);
+        }
+    }
+}
+
+/// The rules with the senders they apply to, the default one first.
+fn scoped_rules(config: &RetentionConfig) -> Vec<(SenderScope, &RetentionRule)> {
+    let overridden: Vec<String> = config.senders.keys().cloned().collect();
+    let mut rules = vec![(SenderScope::Except(overridden), &config.default)];
+    for (sender, rule) in config.senders.iter() {
+        rules.push((SenderScope::Only(sender.clone()), rule));
+    }
+    rules
+}
+
+async fn purge(state: &web::Data<AppState>) -> Result<()> {
+    for (scope, rule) in scoped_rules(&state.config.retention) {
+        apply_rule(state, scope, rule).await?;
+    }
+    Ok(())
+}
+
+async fn apply_rule(
+    state: &web::Data<AppState>,
+    scope: SenderScope,
+    rule: &RetentionRule,
+) -> Result<()> {
+    let batch_size = state.config.retention.batch_size;
+    let now = unix_timestamp();
+
+    if let Some(days) = rule.delete_after_days {
+        let before = now - days as i64 * SECONDS_PER_DAY;
+        let mut deleted = 0;
+        loop {
+            let db_state = state.clone();
+            let scope = scope.clone();
+            let ids = web::block(move || {
+                let con = db_state.db_pool.get()?;
+                let ids = actions::delete_messages_before(&scope, before, batch_size, &con)?;
+                webhook::actions::delete_deliveries_by_messages(&ids, &con)?;
+                Ok(ids)
+            })
+            .await?;
+            deleted += ids.len();
+            // invalidate cache
+            if !ids.is_empty() {
+                let keys: Vec<String> = ids.iter().map(redis_key).collect();
+                let mut redis = state.redis_connection().await?;
+                let _: () = redis.del(keys).await?;
+            }
+            if (ids.len() as i64) < batch_size {
+                break;
+            }
+        }
+        if deleted > 0 {
+            log::info!("Deleted {} messages past retention", deleted);
+        }
+    }
+
+    if let Some(days) = rule.anonymise_after_days {
+        let before = now - days as i64 * SECONDS_PER_DAY;
+        let mut anonymised = 0;
+        loop {
+            let db_state = state.clone();
+            let scope = scope.clone();
+            let updated = web::block(move || {
+                let con = db_state.db_pool.get()?;
+                actions::anonymise_messages_before(&scope, before, batch_size, &con)
+            })
+            .await?;
+            anonymised += updated;
+            if (updated as i64) < batch_size {
+                break;
+            }
+        }
+        if anonymised > 0 {
+            log::info!("Anonymised {} messages past retention", anonymised);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scoped_rules() {
+        let mut config = RetentionConfig::default();
+        let rule = RetentionRule {
+            delete_after_days: Some(7),
+            anonymise_after_days: None,
+        };
+        config.senders.insert("open_id".to_owned(), rule);
+        let rules = scoped_rules(&config);
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].0, SenderScope::Except(vec!["open_id".to_owned()]));
+        assert_eq!(rules[0].1.delete_after_days, None);
+        assert_eq!(rules[1].0, SenderScope::Only("open_id".to_owned()));
+        assert_eq!(rules[1].1.delete_after_days, Some(7));
+    }
+}
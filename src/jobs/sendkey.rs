@@ -0,0 +1,40 @@
+use actix_web::web;
+
+use crate::errors::Result;
+use crate::routes::message::actions;
+use crate::shared_state::AppState;
+
+const BATCH_SIZE: i64 = 500;
+
+/// Save the send key hashes of receivers subscribed before they were saved, once at startup,
+/// so that they can post to `/hook/{sendkey}/{mapping}` without logging in again.
+pub async fn run(state: web::Data<AppState>) {
+    if let Err(e) = backfill(&state).await {
+        log::error!("Failed to save send keys of receivers: {}", e);
+    }
+}
+
+async fn backfill(state: &web::Data<AppState>) -> Result<()> {
+    let db_state = state.clone();
+    let saved = web::block(move || {
+        let con = db_state.db_pool.get()?;
+        let secret_key = &db_state.config.secret_key;
+        let mut saved = 0;
+        loop {
+            let receivers = actions::find_receivers_without_sendkey(BATCH_SIZE, &con)?;
+            for receiver in receivers.iter() {
+                let key = crate::sendkey::sendkey(secret_key, receiver);
+                actions::set_receiver_sendkey(receiver, &crate::sendkey::hash(&key), &con)?;
+            }
+            saved += receivers.len();
+            if (receivers.len() as i64) < BATCH_SIZE {
+                return Ok(saved);
+            }
+        }
+    })
+    .await?;
+    if saved > 0 {
+        log::info!("Send keys of {} receivers saved", saved);
+    }
+    Ok(())
+}
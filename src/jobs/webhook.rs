@@ -0,0 +1,94 @@
+use actix_web::web;
+use std::time::Duration;
+
+use crate::errors::Result;
+use crate::models::WebhookDelivery;
+use crate::routes::webhook::actions;
+use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+
+/// Max length of the error kept in the delivery log.
+const MAX_ERROR_CHARS: usize = 500;
+
+/// Periodically deliver queued webhook events, retrying failures with backoff.
+pub async fn run(state: web::Data<AppState>) {
+    let config = &state.config.webhook;
+    let mut interval = tokio::time::interval(Duration::from_secs(config.poll_seconds));
+    loop {
+        interval.tick().await;
+        if let Err(e) = poll(&state).await {
+            log::error!("Failed to deliver webhooks: {}", e);
+        }
+    }
+}
+
+async fn poll(state: &web::Data<AppState>) -> Result<()> {
+    let config = &state.config.webhook;
+    let batch_size = config.batch_size;
+    // deliveries are attempted one by one, hold them long enough for the whole batch
+    let lease = (config.timeout_seconds as i64 + 1) * batch_size;
+    loop {
+        let db_state = state.clone();
+        let due = web::block(move || {
+            let con = db_state.db_pool.get()?;
+            actions::claim_due_deliveries(unix_timestamp(), lease, batch_size, &con)
+        })
+        .await?;
+        let claimed = due.len() as i64;
+        for delivery in due {
+            // picked up again when the lease expires
+            let id = delivery.id;
+            if let Err(e) = deliver(state, delivery).await {
+                log::error!("Failed to deliver webhook {}: {}", id, e);
+            }
+        }
+        if claimed < batch_size {
+            return Ok(());
+        }
+    }
+}
+
+async fn deliver(state: &web::Data<AppState>, mut delivery: WebhookDelivery) -> Result<()> {
+    let config = &state.config.webhook;
+    let sendkey = crate::sendkey::sendkey(&state.config.secret_key, &delivery.receiver_id);
+    let timeout = Duration::from_secs(config.timeout_seconds);
+    let result = crate::webhook::post(&delivery.url, &sendkey, &delivery.payload, timeout).await;
+
+    let now = unix_timestamp();
+    delivery.attempts += 1;
+    let error = match result {
+        Ok(status) => {
+            delivery.last_status_code = Some(status.as_u16() as i32);
+            match status.is_success() {
+                true => None,
+                false => Some(format!("Unexpected status {}", status)),
+            }
+        }
+        Err(e) => Some(e.to_string().chars().take(MAX_ERROR_CHARS).collect()),
+    };
+    match error {
+        None => {
+            delivery.status = "delivered".to_owned();
+            delivery.delivered_time = Some(now);
+            delivery.last_error = None;
+        }
+        Some(error) => {
+            log::warn!("Webhook delivery {} failed: {}", delivery.id, error);
+            delivery.last_error = Some(error);
+            if delivery.attempts >= config.max_attempts {
+                delivery.status = "failed".to_owned();
+            } else {
+                let backoff = config.retry_seconds << (delivery.attempts - 1).min(16);
+                delivery.next_time = now + backoff;
+            }
+        }
+    }
+
+    let db_state = state.clone();
+    web::block(move || {
+        let con = db_state.db_pool.get()?;
+        actions::update_delivery(&delivery, &con)
+    })
+    .await?;
+    Ok(())
+}
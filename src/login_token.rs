@@ -0,0 +1,81 @@
+use crate::utils::{hmac_sha256_hex, random_hex, secure_eq, unix_timestamp};
+
+/// A short-lived login token issued when a user scans a login scene.
+///
+/// Encoded as `{open_id}.{expires}.{nonce}.{signature}` where the signature is
+/// the HMAC-SHA256 of the rest with the server secret key. The nonce is also
+/// saved in redis so that a token can only be exchanged once.
+#[derive(Debug, PartialEq)]
+pub struct LoginToken {
+    pub open_id: String,
+    pub expires: u64,
+    pub nonce: String,
+}
+
+impl LoginToken {
+    pub fn new(open_id: String, ttl: u64) -> Self {
+        LoginToken {
+            open_id,
+            expires: unix_timestamp() as u64 + ttl,
+            nonce: random_hex(16),
+        }
+    }
+
+    fn payload(&self) -> String {
+        format!("{}.{}.{}", self.open_id, self.expires, self.nonce)
+    }
+
+    pub fn encode(&self, secret_key: &str) -> String {
+        let payload = self.payload();
+        let signature = hmac_sha256_hex(secret_key, &payload);
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Decode and verify a token, returns None if it's malformed, forged or expired.
+    pub fn decode(token: &str, secret_key: &str) -> Option<Self> {
+        let mut parts = token.rsplitn(4, '.');
+        let signature = parts.next()?;
+        let nonce = parts.next()?;
+        let expires = parts.next()?.parse::<u64>().ok()?;
+        let open_id = parts.next()?;
+        let token = LoginToken {
+            open_id: open_id.to_owned(),
+            expires,
+            nonce: nonce.to_owned(),
+        };
+        if !secure_eq(&hmac_sha256_hex(secret_key, &token.payload()), signature) {
+            log::warn!("Login token with bad signature");
+            return None;
+        }
+        if token.expires < unix_timestamp() as u64 {
+            return None;
+        }
+        Some(token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let token = LoginToken::new("open.id".to_owned(), 60);
+        let encoded = token.encode("key");
+        assert_eq!(LoginToken::decode(&encoded, "key"), Some(token));
+        // bad key
+        assert_eq!(LoginToken::decode(&encoded, "bad_key"), None);
+        // tampered
+        let tampered = encoded.replacen("open", "evil", 1);
+        assert_eq!(LoginToken::decode(&tampered, "key"), None);
+        assert_eq!(LoginToken::decode("garbage", "key"), None);
+    }
+
+    #[test]
+    fn test_expired() {
+        let mut token = LoginToken::new("open_id".to_owned(), 0);
+        token.expires -= 1;
+        let encoded = token.encode("key");
+        assert_eq!(LoginToken::decode(&encoded, "key"), None);
+    }
+}
@@ -7,14 +7,27 @@ extern crate diesel;
 use actix_web::middleware::Logger;
 use actix_web::web;
 
+mod ack_token;
 mod config;
+mod delivery;
+mod email;
 mod errors;
+mod html;
+mod jobs;
 mod logging;
+mod login_token;
 mod models;
+mod preferences;
+mod qr_image;
+mod quota;
+mod rate_limit;
 mod routes;
+mod scene_notifier;
 mod schema;
+mod sendkey;
 mod shared_state;
 mod utils;
+mod webhook;
 mod wechat;
 
 #[actix_rt::main]
@@ -30,6 +43,7 @@ async fn main() -> std::io::Result<()> {
     let state = shared_state::AppState::from_config(config);
     // pre-wrap with Arc to avoid clone state
     let app_data = web::Data::new(state);
+    jobs::spawn_all(app_data.clone());
 
     let server = HttpServer::new(move || {
         App::new()
@@ -39,7 +53,13 @@ async fn main() -> std::io::Result<()> {
                 web::scope(&root_url)
                     .configure(routes::scene::configure)
                     .configure(routes::message::configure)
-                    .configure(routes::callback::configure),
+                    .configure(routes::callback::configure)
+                    .configure(routes::login::configure)
+                    .configure(routes::webhook::configure)
+                    .configure(routes::preferences::configure)
+                    .configure(routes::hook::configure)
+                    .configure(routes::alertmanager::configure)
+                    .configure(routes::admin::configure),
             )
             .default_service(web::route().to(routes::default_handler))
     })
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 
-use crate::schema::messages;
+use crate::schema::{
+    delivery_channels, digests, escalations, hook_mappings, message_views, messages,
+    receiver_accounts, receiver_emails, receiver_preferences, webhook_deliveries, webhooks,
+};
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
@@ -17,4 +20,233 @@ pub struct Message {
     pub created_time: i64,
     pub ip: String,
     pub UA: String,
+
+    /// format of the body, see `BodyFormat`
+    pub format: String,
+
+    /// the message is gone after this time
+    pub expires_at: Option<i64>,
+    /// the message is gone and its content erased if revoked
+    pub revoked_time: Option<i64>,
+
+    /// when and by which receiver the message was acknowledged
+    pub acked_time: Option<i64>,
+    pub acked_by: Option<String>,
+    /// url to notify when the message is acknowledged
+    pub ack_webhook: Option<String>,
+    /// msgid returned by wechat, to match the delivery report
+    pub wechat_msg_id: Option<i64>,
+
+    /// identical messages until `dedup_until` are counted as duplicates of this one
+    pub dedup_key: Option<String>,
+    pub dedup_until: Option<i64>,
+    pub duplicates: i32,
+    /// the window is over and the follow-up pushed
+    pub dedup_closed: bool,
+
+    /// not pushed yet as the quota is reached
+    pub queued: bool,
+
+    /// low, normal, high or critical, see `Priority`
+    pub priority: String,
+    /// held in quiet hours or for digest mode, to be pushed in a digest at this time
+    pub held_until: Option<i64>,
+    /// the digest the message was pushed in
+    pub digest_id: Option<Uuid>,
+    /// the delivery channel the message was pushed by, see `ChannelKind`
+    pub channel: Option<String>,
+    /// a queued message is being pushed till then
+    pub queue_lease: Option<i64>,
+    /// the receiver whose send key sent the message, if sent with one
+    pub sender_id: Option<String>,
+}
+
+/// A full-text search hit, title and snippet are highlighted by `ts_headline`.
+#[derive(Debug, QueryableByName)]
+pub struct SearchResult {
+    #[sql_type = "diesel::sql_types::Uuid"]
+    pub id: Uuid,
+    #[sql_type = "diesel::sql_types::Text"]
+    pub title: String,
+    #[sql_type = "diesel::sql_types::Text"]
+    pub snippet: String,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub created_time: i64,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "message_views"]
+pub struct NewMessageView {
+    pub message_id: Uuid,
+    pub viewed_time: i64,
+    pub client: String,
+}
+
+/// Views of a message by one kind of client.
+#[derive(Debug, QueryableByName)]
+pub struct ViewStats {
+    #[sql_type = "diesel::sql_types::Text"]
+    pub client: String,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub count: i64,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub first_viewed_time: i64,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub last_viewed_time: i64,
+}
+
+/// Resend a message until it's viewed, see `jobs::escalation`.
+#[derive(Debug, Clone, Queryable)]
+pub struct Escalation {
+    pub id: i64,
+    pub message_id: Uuid,
+    /// receivers to escalate to after resending to the original receiver
+    pub receivers: Vec<String>,
+    /// steps taken so far
+    pub step: i32,
+    pub max_steps: i32,
+    pub interval_seconds: i64,
+    pub next_time: i64,
+    /// pending, seen, cancelled or exhausted
+    pub status: String,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "escalations"]
+pub struct NewEscalation {
+    pub message_id: Uuid,
+    pub receivers: Vec<String>,
+    pub max_steps: i32,
+    pub interval_seconds: i64,
+    pub next_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Queryable)]
+pub struct Webhook {
+    pub id: i64,
+    /// the webhook belongs to the sender with the send key of this receiver
+    #[serde(skip)]
+    pub receiver_id: String,
+    pub url: String,
+    /// events to deliver, all of them if empty
+    pub events: Vec<String>,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "webhooks"]
+pub struct NewWebhook {
+    pub receiver_id: String,
+    pub url: String,
+    pub events: Vec<String>,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Queryable)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_id: Option<i64>,
+    #[serde(skip)]
+    pub receiver_id: String,
+    pub message_id: Uuid,
+    pub url: String,
+    pub event: String,
+    #[serde(skip)]
+    pub payload: String,
+    /// pending, delivered or failed
+    pub status: String,
+    pub attempts: i32,
+    pub next_time: i64,
+    pub created_time: i64,
+    pub delivered_time: Option<i64>,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[table_name = "webhook_deliveries"]
+pub struct NewWebhookDelivery {
+    pub webhook_id: Option<i64>,
+    pub receiver_id: String,
+    pub message_id: Uuid,
+    pub url: String,
+    pub event: String,
+    pub payload: String,
+    pub next_time: i64,
+    pub created_time: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Queryable, Insertable, AsChangeset)]
+#[table_name = "receiver_preferences"]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct ReceiverPreferences {
+    #[serde(skip)]
+    pub receiver_id: String,
+    /// IANA time zone of the quiet hours
+    pub timezone: String,
+    /// quiet hours in minutes after midnight
+    pub quiet_start: Option<i32>,
+    pub quiet_end: Option<i32>,
+    /// messages below this priority are held in quiet hours
+    pub min_priority: String,
+    pub updated_time: i64,
+    /// push messages in a digest every this many minutes
+    pub digest_interval: Option<i32>,
+}
+
+#[derive(Debug, Clone, Queryable, Insertable)]
+#[table_name = "digests"]
+pub struct Digest {
+    pub id: Uuid,
+    pub receiver_id: String,
+    pub created_time: i64,
+}
+
+/// The official account a receiver subscribed to.
+#[derive(Debug, Clone, Queryable, Insertable, AsChangeset)]
+#[table_name = "receiver_accounts"]
+pub struct ReceiverAccount {
+    pub receiver_id: String,
+    pub account: String,
+    pub updated_time: i64,
+    /// the send key of the receiver, to find them by it
+    pub sendkey: Option<String>,
+}
+
+/// A mapping of incoming json to messages saved by a receiver.
+#[derive(Debug, Clone, Queryable, Insertable, AsChangeset)]
+#[table_name = "hook_mappings"]
+pub struct ReceiverHookMapping {
+    pub receiver_id: String,
+    pub name: String,
+    /// `config::HookMapping` as json
+    pub mapping: String,
+    pub updated_time: i64,
+}
+
+/// The verified email address of a receiver.
+#[derive(Debug, Clone, Queryable, Insertable, AsChangeset)]
+#[table_name = "receiver_emails"]
+pub struct ReceiverEmail {
+    pub receiver_id: String,
+    pub address: String,
+    /// also emailed when another channel took the message
+    pub always: bool,
+    pub verified_time: i64,
+}
+
+/// A delivery channel in the fallback chain of a receiver.
+#[derive(Debug, Clone, Serialize, Deserialize, Queryable, Insertable)]
+#[table_name = "delivery_channels"]
+pub struct DeliveryChannel {
+    #[serde(skip)]
+    pub receiver_id: String,
+    /// tried in ascending order
+    #[serde(skip)]
+    pub position: i32,
+    pub kind: String,
+    /// name of the official account or WeCom app, the receiver's one if absent
+    pub account: Option<String>,
+    /// WeCom user id, or url of the webhook
+    pub target: Option<String>,
 }
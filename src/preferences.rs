@@ -0,0 +1,167 @@
+//! Delivery preferences of receivers.
+//!
+//! In the quiet hours of a receiver, messages below their minimum priority are
+//! held and pushed together in a digest when the quiet hours end. In digest
+//! mode, all but critical messages are held till the next interval boundary.
+use chrono::{Duration, Offset, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+use crate::models::ReceiverPreferences;
+
+/// Priority of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    /// never held
+    Critical,
+}
+
+impl Priority {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Critical => "critical",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "low" => Some(Priority::Low),
+            "normal" => Some(Priority::Normal),
+            "high" => Some(Priority::High),
+            "critical" => Some(Priority::Critical),
+            _ => None,
+        }
+    }
+}
+
+/// Parse `HH:MM` to minutes after midnight.
+pub fn parse_time(s: &str) -> Option<i32> {
+    let mut parts = s.splitn(2, ':');
+    let hour: i32 = parts.next()?.trim().parse().ok()?;
+    let minute: i32 = parts.next()?.trim().parse().ok()?;
+    match (0..24).contains(&hour) && (0..60).contains(&minute) {
+        true => Some(hour * 60 + minute),
+        false => None,
+    }
+}
+
+/// Format minutes after midnight as `HH:MM`.
+pub fn format_time(minutes: i32) -> String {
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+/// The end of the quiet hours if `now` is in them.
+pub fn quiet_until(now: i64, timezone: Tz, start: i32, end: i32) -> Option<i64> {
+    let local = Utc.timestamp(now, 0).with_timezone(&timezone);
+    let minute = (local.hour() * 60 + local.minute()) as i32;
+    let quiet = match start <= end {
+        true => start <= minute && minute < end,
+        // wraps around midnight
+        false => minute >= start || minute < end,
+    };
+    if !quiet {
+        return None;
+    }
+    let date = match minute < end {
+        true => local.date(),
+        false => local.date() + Duration::days(1),
+    };
+    let until = date
+        .naive_local()
+        .and_hms((end / 60) as u32, (end % 60) as u32, 0);
+    // the end may be skipped or repeated by daylight saving
+    let until = timezone
+        .from_local_datetime(&until)
+        .earliest()
+        .map(|t| t.timestamp())
+        .unwrap_or(now + 60 * 60);
+    Some(until)
+}
+
+/// Digest intervals are between 5 minutes and a day.
+pub const MIN_DIGEST_INTERVAL: i32 = 5;
+pub const MAX_DIGEST_INTERVAL: i32 = 24 * 60;
+
+/// The next boundary of the digest interval after `now`, aligned to local midnight.
+pub fn next_digest(now: i64, timezone: Tz, interval: i32) -> i64 {
+    let offset = timezone
+        .offset_from_utc_datetime(&Utc.timestamp(now, 0).naive_utc())
+        .fix()
+        .local_minus_utc() as i64;
+    let interval = interval as i64 * 60;
+    ((now + offset) / interval + 1) * interval - offset
+}
+
+/// When to push a message of the priority sent at `now`, None for right away.
+pub fn hold_until(preferences: &ReceiverPreferences, priority: Priority, now: i64) -> Option<i64> {
+    if priority == Priority::Critical {
+        return None;
+    }
+    let timezone: Tz = preferences.timezone.parse().ok()?;
+    let digest = preferences
+        .digest_interval
+        .filter(|i| (MIN_DIGEST_INTERVAL..=MAX_DIGEST_INTERVAL).contains(i))
+        .map(|interval| next_digest(now, timezone, interval));
+    let min_priority = Priority::parse(&preferences.min_priority).unwrap_or(Priority::Critical);
+    let quiet = match (preferences.quiet_start, preferences.quiet_end) {
+        (Some(start), Some(end)) if start != end && priority < min_priority => {
+            quiet_until(now, timezone, start, end)
+        }
+        _ => None,
+    };
+    // the later one, as held messages are pushed together anyway
+    digest.max(quiet)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("22:30"), Some(22 * 60 + 30));
+        assert_eq!(parse_time("7:05"), Some(7 * 60 + 5));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("noon"), None);
+        assert_eq!(format_time(7 * 60 + 5), "07:05");
+    }
+
+    #[test]
+    fn test_quiet_until() {
+        let tz: Tz = "Asia/Shanghai".parse().unwrap();
+        // 2020-06-01 23:00 in Shanghai
+        let night = tz.ymd(2020, 6, 1).and_hms(23, 0, 0).timestamp();
+        let morning = tz.ymd(2020, 6, 2).and_hms(7, 0, 0).timestamp();
+        assert_eq!(quiet_until(night, tz, 22 * 60, 7 * 60), Some(morning));
+        // after midnight
+        let early = tz.ymd(2020, 6, 2).and_hms(3, 0, 0).timestamp();
+        assert_eq!(quiet_until(early, tz, 22 * 60, 7 * 60), Some(morning));
+        // daytime
+        let noon = tz.ymd(2020, 6, 2).and_hms(12, 0, 0).timestamp();
+        assert_eq!(quiet_until(noon, tz, 22 * 60, 7 * 60), None);
+        // not wrapping
+        let end = tz.ymd(2020, 6, 2).and_hms(14, 0, 0).timestamp();
+        assert_eq!(quiet_until(noon, tz, 12 * 60, 14 * 60), Some(end));
+    }
+
+    #[test]
+    fn test_next_digest() {
+        let tz: Tz = "Asia/Shanghai".parse().unwrap();
+        let now = tz.ymd(2020, 6, 2).and_hms(9, 20, 0).timestamp();
+        let hour = tz.ymd(2020, 6, 2).and_hms(10, 0, 0).timestamp();
+        assert_eq!(next_digest(now, tz, 60), hour);
+        // daily digests at local midnight
+        let midnight = tz.ymd(2020, 6, 3).and_hms(0, 0, 0).timestamp();
+        assert_eq!(next_digest(now, tz, 24 * 60), midnight);
+        // on a boundary
+        assert_eq!(next_digest(hour, tz, 60), hour + 60 * 60);
+    }
+}
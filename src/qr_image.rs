@@ -0,0 +1,112 @@
+use failure::ResultExt;
+use image::codecs::png::PngEncoder;
+use image::ColorType;
+use qrcode::{Color, QrCode};
+
+use crate::errors::{Error, Result};
+
+/// A QR code as a square matrix of modules, with a quiet zone (margin) around it.
+pub struct QrMatrix {
+    modules: Vec<Color>,
+    width: u32,
+    margin: u32,
+}
+
+impl QrMatrix {
+    pub fn new(content: &str, margin: u32) -> Result<Self> {
+        let code = QrCode::new(content.as_bytes()).context("Failed to encode QR code")?;
+        Ok(QrMatrix {
+            width: code.width() as u32,
+            modules: code.to_colors(),
+            margin,
+        })
+    }
+
+    /// Width in modules, including margins on both sides.
+    fn full_width(&self) -> u32 {
+        self.width + 2 * self.margin
+    }
+
+    fn is_dark(&self, x: u32, y: u32) -> bool {
+        let (x, y) = (x.wrapping_sub(self.margin), y.wrapping_sub(self.margin));
+        if x >= self.width || y >= self.width {
+            return false;
+        }
+        self.modules[(y * self.width + x) as usize] == Color::Dark
+    }
+
+    /// Render as PNG whose width is at most `size` pixels.
+    ///
+    /// Fails if `size` is less than 1 pixel per module.
+    pub fn to_png(&self, size: u32) -> Result<Vec<u8>> {
+        let module_size = size / self.full_width();
+        if module_size == 0 {
+            return Err(Error::BadRequest(format!(
+                "Size must be at least {} pixels",
+                self.full_width()
+            )));
+        }
+        let pixels = self.full_width() * module_size;
+        let mut raw = Vec::with_capacity((pixels * pixels) as usize);
+        for y in 0..pixels {
+            for x in 0..pixels {
+                raw.push(match self.is_dark(x / module_size, y / module_size) {
+                    true => 0,
+                    false => 255,
+                });
+            }
+        }
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png)
+            .encode(&raw, pixels, pixels, ColorType::L8)
+            .context("Failed to encode PNG")?;
+        Ok(png)
+    }
+
+    /// Render as SVG with the given width and height in pixels.
+    pub fn to_svg(&self, size: u32) -> String {
+        let full_width = self.full_width();
+        let mut path = String::new();
+        for y in 0..full_width {
+            for x in 0..full_width {
+                if self.is_dark(x, y) {
+                    path.push_str(&format!("M{},{}h1v1h-1z", x, y));
+                }
+            }
+        }
+        format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+                r#"<svg xmlns="http://www.w3.org/2000/svg" version="1.1" width="{size}" height="{size}" viewBox="0 0 {w} {w}" shape-rendering="crispEdges">"#,
+                r#"<rect width="{w}" height="{w}" fill="white"/>"#,
+                r#"<path d="{path}" fill="black"/>"#,
+                "</svg>"
+            ),
+            size = size,
+            w = full_width,
+            path = path
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let qr = QrMatrix::new("http://weixin.qq.com/q/test", 2).unwrap();
+        assert_eq!(qr.full_width(), qr.width + 4);
+        // margin is light
+        assert!(!qr.is_dark(0, 0));
+        // top left finder pattern is dark
+        assert!(qr.is_dark(2, 2));
+
+        let png = qr.to_png(256).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+        assert!(qr.to_png(qr.full_width() - 1).is_err());
+        let svg = qr.to_svg(256);
+        assert!(svg.contains(r#"width="256""#));
+        assert!(svg.contains("M2,2h1v1h-1z"));
+    }
+}
@@ -0,0 +1,218 @@
+//! Tracking of the account-wide daily quota of wechat api calls.
+//!
+//! Calls are counted per account per api per day in redis, with warnings logged
+//! when the usage reaches the configured ratios of the limits. Once wechat
+//! reports the quota of a push api of an account is reached, the api of the
+//! account switches to queue-only mode till the quota resets: messages are
+//! saved but not pushed by it, and pushed later by `jobs::queue`.
+use chrono::{Duration, FixedOffset, Utc};
+use redis::AsyncCommands;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+
+use crate::errors::Result;
+use crate::shared_state::AppState;
+use crate::wechat::errors::WechatError;
+
+/// errcode of wechat api when the daily quota is reached
+pub const QUOTA_REACHED: u64 = 45009;
+/// the quota resets at midnight in Beijing
+const UTC_OFFSET_SECONDS: i32 = 8 * 60 * 60;
+
+/// Wechat apis with a daily quota.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Api {
+    TemplateSend,
+    QrcodeCreate,
+    Token,
+    ClearQuota,
+    CustomSend,
+    /// application messages of WeCom apps
+    WecomMessageSend,
+}
+
+impl Api {
+    pub const ALL: [Api; 6] = [
+        Api::TemplateSend,
+        Api::QrcodeCreate,
+        Api::Token,
+        Api::ClearQuota,
+        Api::CustomSend,
+        Api::WecomMessageSend,
+    ];
+
+    /// Apis pushing messages, which switch to queue-only mode once their quota is reached.
+    pub const PUSH: [Api; 3] = [Api::TemplateSend, Api::CustomSend, Api::WecomMessageSend];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Api::TemplateSend => "message/template/send",
+            Api::QrcodeCreate => "qrcode/create",
+            Api::Token => "cgi-bin/token",
+            Api::ClearQuota => "cgi-bin/clear_quota",
+            Api::CustomSend => "message/custom/send",
+            Api::WecomMessageSend => "wecom/message/send",
+        }
+    }
+}
+
+/// The day of the quota, and seconds till it resets.
+fn quota_day() -> (String, usize) {
+    let now = Utc::now().with_timezone(&FixedOffset::east(UTC_OFFSET_SECONDS));
+    let tomorrow = (now.date() + Duration::days(1)).and_hms(0, 0, 0);
+    let seconds = (tomorrow - now).num_seconds().max(1) as usize;
+    (now.format("%Y%m%d").to_string(), seconds)
+}
+
+fn usage_key(account: &str, day: &str, api: Api) -> String {
+    format!("wxpush:quota:{}:{}:{}", account, day, api.as_str())
+}
+
+fn queue_only_key(account: &str, api: Api) -> String {
+    format!("wxpush:quota:queue_only:{}:{}", account, api.as_str())
+}
+
+async fn incr(state: &AppState, account: &str, api: Api, count: u64) -> Result<()> {
+    let (day, _) = quota_day();
+    let key = usage_key(account, &day, api);
+    let mut con = state.redis_connection().await?;
+    let usage: u64 = con.incr(&key, count).await?;
+    // keep yesterday's usage for a while
+    let _: () = con.expire(&key, 2 * 24 * 60 * 60).await?;
+    if let Some(limit) = state.config.quota.limits.get(api.as_str()) {
+        for ratio in state.config.quota.warn_ratios.iter() {
+            let threshold = (*limit as f64 * ratio).ceil() as u64;
+            if usage >= threshold && usage - count < threshold {
+                log::warn!(
+                    "Wechat api {} of account {} used {} of the daily limit {}",
+                    api.as_str(),
+                    account,
+                    usage,
+                    limit
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Count a call of the api by the account, along with tokens applied for by its token manager meanwhile.
+///
+/// Failures are logged, as tracking must not fail the call itself.
+pub async fn record(state: &AppState, account: &str, api: Api) {
+    let mut counts = vec![(api, 1)];
+    if let Some(manager) = state.token_managers.get(account) {
+        let tokens = manager.lock().await.take_issued();
+        if tokens > 0 {
+            counts.push((Api::Token, tokens));
+        }
+    }
+    for (api, count) in counts {
+        if let Err(e) = incr(state, account, api, count).await {
+            log::warn!("Failed to count calls of {}: {}", api.as_str(), e);
+        }
+    }
+}
+
+/// Calls of each api by the account today.
+pub async fn usage(state: &AppState, account: &str) -> Result<HashMap<&'static str, u64>> {
+    let (day, _) = quota_day();
+    let mut con = state.redis_connection().await?;
+    let mut usage = HashMap::new();
+    for api in Api::ALL.iter() {
+        let count: Option<u64> = con.get(usage_key(account, &day, *api)).await?;
+        usage.insert(api.as_str(), count.unwrap_or(0));
+    }
+    Ok(usage)
+}
+
+pub async fn is_queue_only(state: &AppState, account: &str, api: Api) -> Result<bool> {
+    let mut con = state.redis_connection().await?;
+    Ok(con.exists(queue_only_key(account, api)).await?)
+}
+
+/// Push apis of the account in queue-only mode.
+pub async fn queue_only_apis(state: &AppState, account: &str) -> Result<Vec<&'static str>> {
+    let mut apis = Vec::new();
+    for api in Api::PUSH.iter() {
+        if is_queue_only(state, account, *api).await? {
+            apis.push(api.as_str());
+        }
+    }
+    Ok(apis)
+}
+
+/// Queue messages pushed by the api of the account until the quota resets.
+async fn set_queue_only(state: &AppState, account: &str, api: Api) -> Result<()> {
+    let (_, seconds) = quota_day();
+    let mut con = state.redis_connection().await?;
+    let _: () = con.set_ex(queue_only_key(account, api), 1, seconds).await?;
+    Ok(())
+}
+
+/// Leave queue-only mode for all the push apis of the account.
+pub async fn clear_queue_only(state: &AppState, account: &str) -> Result<()> {
+    let mut con = state.redis_connection().await?;
+    let keys: Vec<String> = Api::PUSH
+        .iter()
+        .map(|api| queue_only_key(account, *api))
+        .collect();
+    let _: () = con.del(keys).await?;
+    Ok(())
+}
+
+/// Result of pushing a message.
+pub enum Push {
+    /// the response of wechat, or of the channel
+    Sent(Value),
+    /// not pushed as the quota is reached, to be pushed later
+    Queued,
+}
+
+/// Make a push call of the api by the account unless the api is in queue-only mode, counting the call.
+///
+/// The api switches to queue-only mode once wechat reports its quota is reached.
+pub async fn push<F>(
+    state: &AppState,
+    account: &str,
+    api: Api,
+    call: F,
+) -> std::result::Result<Push, WechatError>
+where
+    F: Future<Output = std::result::Result<Value, WechatError>>,
+{
+    match is_queue_only(state, account, api).await {
+        Ok(true) => return Ok(Push::Queued),
+        Ok(false) => {}
+        Err(e) => log::warn!("Failed to check queue-only mode: {}", e),
+    }
+    let result = call.await;
+    record(state, account, api).await;
+    match result {
+        Err(WechatError::Wechat { errcode, .. }) if errcode == QUOTA_REACHED => {
+            log::error!(
+                "Daily quota of {} of account {} reached, queueing messages",
+                api.as_str(),
+                account
+            );
+            if let Err(e) = set_queue_only(state, account, api).await {
+                log::error!("Failed to switch to queue-only mode: {}", e);
+            }
+            Ok(Push::Queued)
+        }
+        result => result.map(Push::Sent),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_quota_day() {
+        let (day, seconds) = quota_day();
+        assert_eq!(day.len(), 8);
+        assert!((1..=24 * 60 * 60).contains(&seconds));
+    }
+}
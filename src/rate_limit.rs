@@ -0,0 +1,113 @@
+//! Token bucket rate limits kept in redis.
+//!
+//! A bucket holds at most `burst` tokens and is refilled at `rate` tokens per
+//! second, each request takes one token. The bucket is updated atomically by a
+//! lua script, so the limits hold across server instances.
+use actix_web::HttpRequest;
+use std::time::SystemTime;
+
+use crate::config::RateLimitRule;
+use crate::errors::{Error, Result};
+use crate::shared_state::AppState;
+use crate::utils::client_ip;
+
+/// Returns 0 if a token is taken, or else the seconds to wait for one.
+const TOKEN_BUCKET: &str = r"
+local rate = tonumber(ARGV[1])
+local burst = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'time')
+local tokens = tonumber(bucket[1]) or burst
+local time = tonumber(bucket[2]) or now
+tokens = math.min(burst, tokens + math.max(0, now - time) / 1000 * rate)
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) / rate)
+end
+redis.call('HMSET', KEYS[1], 'tokens', tostring(tokens), 'time', now)
+redis.call('EXPIRE', KEYS[1], math.ceil(burst / rate) + 1)
+return wait
+";
+
+/// What a bucket is keyed by.
+#[derive(Debug, Clone, Copy)]
+pub enum Bucket {
+    /// the send key authorising the request
+    Sender,
+    /// the open id a message is sent to
+    Receiver,
+    /// the client ip
+    Ip,
+    /// the open id verification emails are requested by
+    EmailReceiver,
+    /// the address verification emails are sent to
+    EmailAddress,
+}
+
+impl Bucket {
+    fn as_str(self) -> &'static str {
+        match self {
+            Bucket::Sender => "sender",
+            Bucket::Receiver => "receiver",
+            Bucket::Ip => "ip",
+            Bucket::EmailReceiver => "email_receiver",
+            Bucket::EmailAddress => "email_address",
+        }
+    }
+
+    fn rule(self, state: &AppState) -> Option<&RateLimitRule> {
+        let config = &state.config.rate_limit;
+        match self {
+            Bucket::Sender => config.sender.as_ref(),
+            Bucket::Receiver => config.receiver.as_ref(),
+            Bucket::Ip => config.ip.as_ref(),
+            Bucket::EmailReceiver | Bucket::EmailAddress => config.email.as_ref(),
+        }
+    }
+}
+
+/// Marks a request whose client ip took a token already.
+struct IpChecked;
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Take a token from the bucket of `id`, fails with `Error::TooManyRequests` if there's none.
+pub async fn check(state: &AppState, bucket: Bucket, id: &str) -> Result<()> {
+    let rule = match bucket.rule(state) {
+        Some(rule) if rule.rate > 0.0 => rule,
+        _ => return Ok(()),
+    };
+    let mut con = state.redis_connection().await?;
+    let wait: u64 = redis::Script::new(TOKEN_BUCKET)
+        .key(format!("wxpush:ratelimit:{}:{}", bucket.as_str(), id))
+        .arg(rule.rate)
+        .arg(rule.burst)
+        .arg(now_millis())
+        .invoke_async(&mut con)
+        .await?;
+    match wait {
+        0 => Ok(()),
+        wait => {
+            log::warn!("Rate limited {} {}", bucket.as_str(), id);
+            Err(Error::TooManyRequests(wait))
+        }
+    }
+}
+
+/// Take a token from the bucket of the client ip, only once for a request, so that
+/// handlers may check it before validating the request and again when sending messages.
+pub async fn check_ip(state: &AppState, request: &HttpRequest) -> Result<()> {
+    if request.extensions().get::<IpChecked>().is_some() {
+        return Ok(());
+    }
+    request.extensions_mut().insert(IpChecked);
+    let ip = client_ip(request, &state.config.rate_limit.trusted_proxies);
+    check(state, Bucket::Ip, &ip).await
+}
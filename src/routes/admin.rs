@@ -0,0 +1,131 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::DEFAULT_ACCOUNT;
+use crate::errors::{Error, Result};
+use crate::models::ReceiverAccount;
+use crate::quota::{self, Api};
+use crate::sendkey;
+use crate::shared_state::AppState;
+use crate::utils::{secure_eq, unix_timestamp};
+use crate::wechat;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/admin/quota")
+            .name("Wechat api quota")
+            .route(web::get().to(get_quota)),
+    )
+    .service(
+        web::resource("/admin/quota/clear")
+            .name("Clear wechat api quota")
+            .route(web::post().to(clear_quota)),
+    )
+    .service(
+        web::resource("/admin/receivers/{receiver}/account")
+            .name("Account of receiver")
+            .route(web::post().to(set_receiver_account)),
+    );
+}
+
+/// Authorise by the `Authorization: Bearer {admin_key}` header.
+fn authorise(request: &HttpRequest, state: &AppState) -> Result<()> {
+    let unauthorized = || Error::Unauthorized("Invalid admin key".to_owned());
+    let admin_key = state.config.admin_key.as_ref().ok_or_else(unauthorized)?;
+    let key = request
+        .headers()
+        .get(actix_web::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .ok_or_else(unauthorized)?;
+    match secure_eq(admin_key, key) {
+        true => Ok(()),
+        false => Err(unauthorized()),
+    }
+}
+
+#[derive(Deserialize)]
+struct AccountQuery {
+    /// name of the official account, the default one if absent
+    account: Option<String>,
+}
+
+/// Calls of wechat apis by the account today, against the configured limits.
+async fn get_quota(
+    request: HttpRequest,
+    query: web::Query<AccountQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    authorise(&request, &state)?;
+    let account = query.account.as_deref().unwrap_or(DEFAULT_ACCOUNT);
+    state.token_manager(account)?;
+    Ok(HttpResponse::Ok().json(json!({
+        "account": account,
+        "usage": quota::usage(&state, account).await?,
+        "limits": state.config.quota.limits,
+        "queue_only": quota::queue_only_apis(&state, account).await?,
+    })))
+}
+
+/// Reset the daily quota of the account and leave queue-only mode.
+async fn clear_quota(
+    request: HttpRequest,
+    query: web::Query<AccountQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    authorise(&request, &state)?;
+    let account = query.account.as_deref().unwrap_or(DEFAULT_ACCOUNT);
+    // the quota of WeCom apps can not be cleared
+    let app_id = &state
+        .config
+        .account(account)
+        .ok_or_else(|| Error::BadRequest(format!("Unknown account {}", account)))?
+        .app_id;
+    let token_manager = state.token_manager(account)?;
+    let result = wechat::quota::clear_quota(token_manager, app_id).await;
+    quota::record(&state, account, Api::ClearQuota).await;
+    result?;
+    quota::clear_queue_only(&state, account).await?;
+    log::warn!("Wechat api quota of account {} cleared", account);
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+#[derive(Deserialize)]
+struct ReceiverAccountForm {
+    account: String,
+}
+
+/// Push messages to the receiver by the account or WeCom app, and issue the send key of the receiver.
+///
+/// Receivers of official accounts are mapped when they subscribe, while WeCom
+/// user ids are mapped here.
+async fn set_receiver_account(
+    request: HttpRequest,
+    params: web::Path<(String,)>,
+    form: web::Form<ReceiverAccountForm>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    authorise(&request, &state)?;
+    let receiver = params.into_inner().0;
+    let account = form.into_inner().account;
+    state.token_manager(&account)?;
+    let key = sendkey::sendkey(&state.config.secret_key, &receiver);
+    let receiver_account = ReceiverAccount {
+        receiver_id: receiver.clone(),
+        account: account.clone(),
+        updated_time: unix_timestamp(),
+        sendkey: Some(sendkey::hash(&key)),
+    };
+    let db_state = state.clone();
+    web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        crate::routes::message::actions::save_receiver_account(&receiver_account, &con)
+    })
+    .await?;
+    log::info!("Receiver {} mapped to account {}", receiver, account);
+    Ok(HttpResponse::Ok().json(json!({
+        "account": account,
+        "sendkey": key,
+    })))
+}
@@ -0,0 +1,249 @@
+//! The webhook payload of Prometheus Alertmanager, and the message of a group.
+//!
+//! [payload]: https://prometheus.io/docs/alerting/latest/configuration/#webhook_config
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::preferences::Priority;
+use crate::utils::hmac_sha256_hex;
+
+/// A notification of a group of alerts.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub status: String,
+    pub group_key: String,
+    #[serde(default)]
+    pub group_labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub common_labels: BTreeMap<String, String>,
+    #[serde(default, rename = "externalURL")]
+    pub external_url: Option<String>,
+    #[serde(default)]
+    pub truncated_alerts: u64,
+    pub alerts: Vec<Alert>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Alert {
+    pub status: String,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    pub starts_at: Option<String>,
+    pub ends_at: Option<String>,
+    #[serde(rename = "generatorURL")]
+    pub generator_url: Option<String>,
+    #[serde(default)]
+    pub fingerprint: String,
+}
+
+const FIRING: &str = "firing";
+
+/// Priority of a firing alert by its `severity` label.
+fn severity_priority(severity: &str) -> Priority {
+    match severity.to_ascii_lowercase().as_str() {
+        "critical" | "page" | "emergency" => Priority::Critical,
+        "error" | "high" | "warning" => Priority::High,
+        "info" | "low" | "none" => Priority::Low,
+        _ => Priority::Normal,
+    }
+}
+
+/// Escape markdown punctuation in label values and alike.
+fn escape_markdown(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\`*_[]<".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// RFC 3339 time in local time, None for the zero time of alerts not ended.
+fn format_time(time: &Option<String>) -> Option<String> {
+    let time = DateTime::parse_from_rfc3339(time.as_deref()?).ok()?;
+    if time.timestamp() <= 0 {
+        return None;
+    }
+    Some(
+        time.with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string(),
+    )
+}
+
+impl Notification {
+    pub fn is_firing(&self) -> bool {
+        self.status == FIRING
+    }
+
+    fn firing(&self) -> impl Iterator<Item = &Alert> {
+        self.alerts.iter().filter(|a| a.status == FIRING)
+    }
+
+    /// The alert name of the group, or its labels if grouped otherwise.
+    fn name(&self) -> String {
+        let labels = match self.group_labels.is_empty() {
+            true => &self.common_labels,
+            false => &self.group_labels,
+        };
+        if let Some(name) = labels.get("alertname") {
+            return name.clone();
+        }
+        let values: Vec<&str> = labels.values().map(String::as_str).collect();
+        match values.is_empty() {
+            true => format!("{} alerts", self.alerts.len()),
+            false => values.join(" "),
+        }
+    }
+
+    /// The highest severity of the firing alerts.
+    fn severity(&self) -> Option<&str> {
+        self.firing()
+            .filter_map(|a| a.labels.get("severity"))
+            .max_by_key(|s| severity_priority(s))
+            .map(String::as_str)
+    }
+
+    /// Firing alerts are pushed by their highest severity, resolved ones as low.
+    pub fn priority(&self) -> Priority {
+        match (self.is_firing(), self.severity()) {
+            (true, Some(severity)) => severity_priority(severity),
+            (true, None) => Priority::Normal,
+            (false, _) => Priority::Low,
+        }
+    }
+
+    /// e.g. `[FIRING:2] HighLatency (critical)` or `[RESOLVED] HighLatency`
+    pub fn title(&self) -> String {
+        match (self.is_firing(), self.severity()) {
+            (true, Some(severity)) => format!(
+                "[FIRING:{}] {} ({})",
+                self.firing().count() as u64 + self.truncated_alerts,
+                self.name(),
+                severity
+            ),
+            (true, None) => format!(
+                "[FIRING:{}] {}",
+                self.firing().count() as u64 + self.truncated_alerts,
+                self.name()
+            ),
+            (false, _) => format!("[RESOLVED] {}", self.name()),
+        }
+    }
+
+    /// Markdown listing the alerts, firing ones first, shown on the detail page.
+    pub fn body(&self) -> String {
+        let mut alerts: Vec<&Alert> = self.alerts.iter().collect();
+        alerts.sort_by_key(|a| a.status != FIRING);
+        let mut body = String::new();
+        for alert in alerts {
+            let name = alert
+                .labels
+                .get("alertname")
+                .map_or("alert", String::as_str);
+            body.push_str(&format!(
+                "#### [{}] {}\n\n",
+                alert.status,
+                escape_markdown(name)
+            ));
+            for (key, value) in alert.annotations.iter() {
+                body.push_str(&format!(
+                    "- {}: {}\n",
+                    escape_markdown(key),
+                    escape_markdown(value)
+                ));
+            }
+            for (key, value) in alert.labels.iter().filter(|(k, _)| *k != "alertname") {
+                body.push_str(&format!(
+                    "- `{}` = `{}`\n",
+                    key.replace('`', "'"),
+                    value.replace('`', "'")
+                ));
+            }
+            if let Some(time) = format_time(&alert.starts_at) {
+                body.push_str(&format!("- 开始：{}\n", time));
+            }
+            if let Some(time) = format_time(&alert.ends_at) {
+                body.push_str(&format!("- 结束：{}\n", time));
+            }
+            if let Some(url) = alert.generator_url.as_deref().filter(|u| !u.is_empty()) {
+                body.push_str(&format!("- [来源](<{}>)\n", url.replace('>', "%3E")));
+            }
+            body.push('\n');
+        }
+        if self.truncated_alerts > 0 {
+            body.push_str(&format!("另有 {} 条告警未列出\n", self.truncated_alerts));
+        }
+        body
+    }
+
+    /// The same for repeated notifications of the group with the same alerts.
+    pub fn dedup_key(&self) -> String {
+        let mut fingerprints: Vec<&str> =
+            self.alerts.iter().map(|a| a.fingerprint.as_str()).collect();
+        fingerprints.sort_unstable();
+        let data = format!("{}|{}", self.status, fingerprints.join(","));
+        format!("alertmanager:{}", hmac_sha256_hex(&self.group_key, &data))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn notification(status: &str) -> Notification {
+        serde_json::from_value(serde_json::json!({
+            "version": "4",
+            "groupKey": "{}:{alertname=\"HighLatency\"}",
+            "status": status,
+            "receiver": "wxpush",
+            "groupLabels": { "alertname": "HighLatency" },
+            "commonLabels": { "alertname": "HighLatency" },
+            "externalURL": "http://alertmanager:9093",
+            "alerts": [
+                {
+                    "status": status,
+                    "labels": { "alertname": "HighLatency", "severity": "warning", "instance": "a" },
+                    "annotations": { "summary": "p99 > 1s" },
+                    "startsAt": "2020-08-01T10:00:00Z",
+                    "endsAt": "0001-01-01T00:00:00Z",
+                    "generatorURL": "http://prometheus:9090/graph",
+                    "fingerprint": "b"
+                },
+                {
+                    "status": status,
+                    "labels": { "alertname": "HighLatency", "severity": "critical", "instance": "b" },
+                    "annotations": {},
+                    "startsAt": "2020-08-01T10:01:00Z",
+                    "fingerprint": "a"
+                }
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_notification() {
+        let firing = notification("firing");
+        assert_eq!(firing.title(), "[FIRING:2] HighLatency (critical)");
+        assert_eq!(firing.priority(), Priority::Critical);
+        let body = firing.body();
+        assert!(body.contains("- summary: p99 > 1s"));
+        assert!(body.contains("- `instance` = `a`"));
+        assert!(body.contains("- [来源](<http://prometheus:9090/graph>)"));
+        assert!(!body.contains("结束"));
+
+        let resolved = notification("resolved");
+        assert_eq!(resolved.title(), "[RESOLVED] HighLatency");
+        assert_eq!(resolved.priority(), Priority::Low);
+        assert_ne!(firing.dedup_key(), resolved.dedup_key());
+        assert_eq!(firing.dedup_key(), notification("firing").dedup_key());
+    }
+}
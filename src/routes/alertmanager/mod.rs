@@ -0,0 +1,4 @@
+mod alerts;
+mod routes;
+
+pub use routes::configure;
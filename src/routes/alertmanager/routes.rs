@@ -0,0 +1,112 @@
+use crate::errors::{Error, Result};
+use crate::html::body::BodyFormat;
+use crate::html::is_safe_url;
+use crate::rate_limit;
+use crate::shared_state::AppState;
+use crate::utils::hmac_sha256_hex;
+use crate::wechat::template_message::NewMessage;
+use actix_web::{web, HttpRequest, HttpResponse};
+use redis::AsyncCommands;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::alerts::Notification;
+
+/// the firing message of a group is resolved if the group resolves within this
+const GROUP_SECONDS: usize = 7 * 24 * 60 * 60;
+
+/// The token of the last firing message of the group.
+fn group_redis_key(receiver: &str, group_key: &str) -> String {
+    let hash = hmac_sha256_hex("alertmanager", group_key);
+    format!("wxpush:alertmanager:{}:{}", receiver, hash)
+}
+
+/// Stop escalating the firing message of a resolved group.
+async fn resolve_group(state: &web::Data<AppState>, redis_key: &str) -> Result<()> {
+    let mut redis = state.as_ref().redis_connection().await?;
+    let token: Option<String> = redis.get(redis_key).await?;
+    let uuid = match token.and_then(|t| Uuid::parse_str(&t).ok()) {
+        Some(uuid) => uuid,
+        None => return Ok(()),
+    };
+    let _: () = redis.del(redis_key).await?;
+    let db_state = state.clone();
+    let cancelled = web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        crate::routes::message::actions::cancel_escalations(uuid, &con)
+    })
+    .await?;
+    if cancelled > 0 {
+        log::info!("Escalation of message {} cancelled as resolved", uuid);
+    }
+    Ok(())
+}
+
+/// Escalation of firing messages, as for `POST /message`, set in the webhook url.
+#[derive(Deserialize)]
+struct EscalationQuery {
+    escalate_after: Option<u64>,
+    escalate_to: Option<String>,
+    escalate_steps: Option<i32>,
+}
+
+/// POST /alertmanager/{sendkey}, the webhook receiver of Prometheus Alertmanager.
+///
+/// Each notification of a group is sent as a message listing its alerts.
+/// Repeated notifications of the same alerts are deduplicated by `groupKey`,
+/// and once the group resolves its firing message is no longer escalated.
+async fn post_alerts(
+    params: web::Path<(String,)>,
+    query: web::Query<EscalationQuery>,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+    request: HttpRequest,
+) -> Result<HttpResponse> {
+    let sendkey = params.into_inner().0;
+    // the sender bucket is checked once the send key is valid, when sending the message
+    rate_limit::check_ip(&state, &request).await?;
+    let notification: Notification = serde_json::from_slice(&body)
+        .map_err(|e| Error::BadRequest(format!("Bad alertmanager payload: {}", e)))?;
+    if notification.alerts.is_empty() {
+        return Err(Error::BadRequest("No alerts in the payload".to_owned()));
+    }
+    let receiver = crate::routes::hook::find_receiver(&state, &sendkey).await?;
+
+    let redis_key = group_redis_key(&receiver, &notification.group_key);
+    let firing = notification.is_firing();
+    let query = query.into_inner();
+    let message = NewMessage {
+        receiver,
+        title: notification.title(),
+        body: Some(notification.body()),
+        url: notification
+            .external_url
+            .clone()
+            .filter(|url| is_safe_url(url)),
+        format: Some(BodyFormat::Markdown),
+        priority: Some(notification.priority()),
+        dedup_key: Some(notification.dedup_key()),
+        sendkey: Some(sendkey),
+        escalate_after: query.escalate_after.filter(|_| firing),
+        escalate_to: query.escalate_to.filter(|_| firing),
+        escalate_steps: query.escalate_steps.filter(|_| firing),
+        ..Default::default()
+    };
+    let response = crate::routes::message::send(message, state.clone(), &request).await?;
+    // the firing message is escalated until the resolved one is sent
+    if !firing {
+        resolve_group(&state, &redis_key).await?;
+    } else if let Some(token) = response["token"].as_str() {
+        let mut redis = state.as_ref().redis_connection().await?;
+        let _: () = redis.set_ex(&redis_key, token, GROUP_SECONDS).await?;
+    }
+    Ok(HttpResponse::Ok().json(response))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/alertmanager/{sendkey}")
+            .name("alertmanager webhook")
+            .route(web::post().to(post_alerts)),
+    );
+}
@@ -1,21 +1,31 @@
+use crate::config::{WechatConfig, DEFAULT_ACCOUNT};
+use crate::email::{self, Command};
 use crate::errors::{Error, Result};
+use crate::models::ReceiverAccount;
 use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+use crate::webhook::{self, Event};
 use actix_web::{web, HttpResponse};
 use failure::ResultExt;
 use redis::aio::Connection as RedisConnection;
 use redis::AsyncCommands;
+use serde_json::json;
 
 use super::verification::WechatQuery;
 
-/// insert scene_id -> open_id into cache.
+/// insert scene_id -> open_id into cache, and notify the waiters of this scene.
 async fn cache_scene_id_with_openid(
     mut redis_connection: RedisConnection,
     scene: String,
     open_id: String,
 ) -> Result<()> {
     // insert
-    redis_connection
-        .set_ex(format!("scene_{}", scene), open_id, 5 * 60)
+    let _: () = redis_connection
+        .set_ex(format!("scene_{}", scene), &open_id, 5 * 60)
+        .await?;
+    // publish
+    let _: i64 = redis_connection
+        .publish(crate::scene_notifier::channel_name(&scene), open_id)
         .await?;
     Ok(())
 }
@@ -25,15 +35,36 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
         web::resource("/callback")
             .route(web::get().to(echo_get_callback))
             .route(web::post().to(event_callback)),
+    )
+    .service(
+        web::resource("/callback/{account}")
+            .route(web::get().to(echo_get_callback))
+            .route(web::post().to(event_callback)),
     );
 }
 
+/// The account of `/callback/{account}`, the default one for `/callback`.
+fn callback_account(
+    params: Option<web::Path<(String,)>>,
+    state: &AppState,
+) -> Result<(String, WechatConfig)> {
+    let name = params
+        .map(|p| p.into_inner().0)
+        .unwrap_or_else(|| DEFAULT_ACCOUNT.to_owned());
+    match state.config.account(&name) {
+        Some(account) => Ok((name, account.clone())),
+        None => Err(Error::NotFound(format!("Unknown account {}", name))),
+    }
+}
+
 /// GET /callback, return echostr
 async fn echo_get_callback(
+    params: Option<web::Path<(String,)>>,
     query: web::Query<WechatQuery>,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse> {
-    if !query.verify(state.as_ref().config.wechat.token.as_str()) {
+    let (_, account) = callback_account(params, &state)?;
+    if !query.verify(account.token.as_str()) {
         return Err(Error::Unauthorized(
             "Callback signature verification failed".to_owned(),
         ));
@@ -47,11 +78,13 @@ async fn echo_get_callback(
 
 /// POST /callback, event callback
 async fn event_callback(
+    params: Option<web::Path<(String,)>>,
     query: web::Query<WechatQuery>,
     state: web::Data<AppState>,
     body: String,
 ) -> Result<HttpResponse> {
-    if !query.verify(state.as_ref().config.wechat.token.as_str()) {
+    let (name, account) = callback_account(params, &state)?;
+    if !query.verify(account.token.as_str()) {
         return Err(Error::Unauthorized("Verification failed".to_owned()));
     }
     use super::xml_parse::parse_xml_string;
@@ -60,7 +93,8 @@ async fn event_callback(
 
     // |_| Error::internal("Paring callback xml error"))?;
     match data["MsgType"].as_str() {
-        "event" => on_event(state, data).await,
+        "event" => on_event(state, &name, &account, data).await,
+        "text" => on_text(state, data).await,
         t => {
             log::debug!("Unknown Type {}", t);
             Ok(HttpResponse::Ok().body(""))
@@ -68,8 +102,136 @@ async fn event_callback(
     }
 }
 
+/// Report the delivery result of a template message to webhooks.
+async fn on_template_send_finish(
+    state: &web::Data<AppState>,
+    account: &WechatConfig,
+    data: &std::collections::HashMap<String, String>,
+) -> Result<()> {
+    let msg_id = match data.get("MsgID").and_then(|id| id.parse::<i64>().ok()) {
+        Some(msg_id) => msg_id,
+        None => return Ok(()),
+    };
+    let db_state = state.clone();
+    let app_id = account.app_id.clone();
+    let message = web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        crate::routes::message::actions::find_message_by_wechat_msg_id(&app_id, msg_id, &con)
+    })
+    .await?;
+    // resent by escalation, or not saved
+    let message = match message {
+        Some(message) => message,
+        None => return Ok(()),
+    };
+    let status = data.get("Status").map(String::as_str).unwrap_or_default();
+    let (event, data) = match status {
+        "success" => (Event::Delivered, json!({})),
+        _ => (Event::Failed, json!({ "reason": status })),
+    };
+    let receiver = &message.receiver_id;
+    webhook::emit(state, event, receiver, message.id, data, None).await;
+    Ok(())
+}
+
+/// Remember the account of the receiver, to push their messages by it.
+async fn save_receiver_account(
+    state: &web::Data<AppState>,
+    name: &str,
+    open_id: &str,
+) -> Result<()> {
+    let db_state = state.clone();
+    let receiver_account = ReceiverAccount {
+        receiver_id: open_id.to_owned(),
+        account: name.to_owned(),
+        updated_time: unix_timestamp(),
+        sendkey: Some(crate::sendkey::hash(&crate::sendkey::sendkey(
+            &state.config.secret_key,
+            open_id,
+        ))),
+    };
+    web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        crate::routes::message::actions::save_receiver_account(&receiver_account, &con)
+    })
+    .await?;
+    Ok(())
+}
+
+/// A passive reply of text to the sender of a callback message.
+fn text_reply(data: &std::collections::HashMap<String, String>, content: &str) -> HttpResponse {
+    let cdata = |s: &str| format!("<![CDATA[{}]]>", s.replace("]]>", "]]]]><![CDATA[>"));
+    let xml = format!(
+        "<xml><ToUserName>{}</ToUserName><FromUserName>{}</FromUserName>\
+         <CreateTime>{}</CreateTime><MsgType><![CDATA[text]]></MsgType>\
+         <Content>{}</Content></xml>",
+        cdata(&data["FromUserName"]),
+        cdata(&data["ToUserName"]),
+        unix_timestamp(),
+        cdata(content),
+    );
+    HttpResponse::Ok().content_type("application/xml").body(xml)
+}
+
+/// Handle the email commands sent by receivers, ignoring other text.
+async fn on_text(
+    state: web::Data<AppState>,
+    data: std::collections::HashMap<String, String>,
+) -> Result<HttpResponse> {
+    let command = match data.get("Content").and_then(|c| email::parse_command(c)) {
+        Some(command) => command,
+        None => return Ok(HttpResponse::Ok().body("")),
+    };
+    let open_id = data["FromUserName"].clone();
+    let reply = match command {
+        Command::Show => {
+            let db_state = state.clone();
+            let receiver = open_id.clone();
+            let found = web::block(move || {
+                let con = db_state.as_ref().db_pool.get()?;
+                crate::delivery::actions::find_email(&receiver, &con)
+            })
+            .await?;
+            match found {
+                Some(e) => format!("已验证邮箱：{}，回复「email off」取消", e.address),
+                None => "回复「email 邮箱地址」绑定邮箱，推送失败时发送至邮箱；\
+                         加上「always」则每条消息都发送"
+                    .to_owned(),
+            }
+        }
+        Command::Register { address, always } => {
+            match email::request_verification(&state, &open_id, &address, always).await {
+                Ok(address) => format!(
+                    "验证码已发送至 {}，请在 {} 分钟内回复「email 验证码」",
+                    address, state.config.smtp.code_minutes
+                ),
+                Err(Error::BadRequest(msg)) => msg,
+                Err(e) => return Err(e),
+            }
+        }
+        Command::Verify(code) => match email::verify(&state, &open_id, &code).await {
+            Ok(e) => format!("邮箱 {} 验证成功", e.address),
+            Err(Error::BadRequest(msg)) | Err(Error::NotFound(msg)) => msg,
+            Err(e) => return Err(e),
+        },
+        Command::Remove => {
+            let db_state = state.clone();
+            let receiver = open_id.clone();
+            web::block(move || {
+                let con = db_state.as_ref().db_pool.get()?;
+                crate::delivery::actions::delete_email(&receiver, &con)
+            })
+            .await?;
+            "已取消邮箱推送".to_owned()
+        }
+    };
+    Ok(text_reply(&data, &reply))
+}
+
 async fn on_event(
     state: web::Data<AppState>,
+    name: &str,
+    account: &WechatConfig,
     data: std::collections::HashMap<String, String>,
 ) -> Result<HttpResponse> {
     log::debug!("handle callback event");
@@ -77,6 +239,7 @@ async fn on_event(
     match data["Event"].as_str() {
         // on subscribe or scan
         "subscribe" | "scan" => {
+            save_receiver_account(&state, name, &open_id).await?;
             match data.get("EventKey") {
                 None => {}
                 Some(value) => {
@@ -96,6 +259,7 @@ async fn on_event(
                 }
             }
         }
+        "TEMPLATESENDJOBFINISH" => on_template_send_finish(&state, account, &data).await?,
         event => log::debug!("Unknown event {}", event),
     }
     Ok(HttpResponse::Ok().body(""))
@@ -0,0 +1,62 @@
+use crate::errors::Result;
+use crate::models;
+
+use diesel::prelude::*;
+
+pub fn find_mapping(
+    receiver: &str,
+    mapping_name: &str,
+    con: &PgConnection,
+) -> Result<Option<models::ReceiverHookMapping>> {
+    use crate::schema::hook_mappings::dsl::*;
+    let mut found = hook_mappings
+        .filter(receiver_id.eq(receiver))
+        .filter(name.eq(mapping_name))
+        .limit(1)
+        .load::<models::ReceiverHookMapping>(con)?;
+    Ok(found.pop())
+}
+
+pub fn list_mappings(
+    receiver: &str,
+    con: &PgConnection,
+) -> Result<Vec<models::ReceiverHookMapping>> {
+    use crate::schema::hook_mappings::dsl::*;
+    let found = hook_mappings
+        .filter(receiver_id.eq(receiver))
+        .order(name)
+        .load::<models::ReceiverHookMapping>(con)?;
+    Ok(found)
+}
+
+pub fn count_mappings(receiver: &str, con: &PgConnection) -> Result<i64> {
+    use crate::schema::hook_mappings::dsl::*;
+    let count = hook_mappings
+        .filter(receiver_id.eq(receiver))
+        .count()
+        .get_result(con)?;
+    Ok(count)
+}
+
+pub fn save_mapping(saved: &models::ReceiverHookMapping, con: &PgConnection) -> Result<()> {
+    use crate::schema::hook_mappings::dsl::*;
+    diesel::insert_into(hook_mappings)
+        .values(saved)
+        .on_conflict((receiver_id, name))
+        .do_update()
+        .set(saved)
+        .execute(con)?;
+    Ok(())
+}
+
+/// Delete the mapping, returning whether there was one.
+pub fn delete_mapping(receiver: &str, mapping_name: &str, con: &PgConnection) -> Result<bool> {
+    use crate::schema::hook_mappings::dsl::*;
+    let deleted = diesel::delete(
+        hook_mappings
+            .filter(receiver_id.eq(receiver))
+            .filter(name.eq(mapping_name)),
+    )
+    .execute(con)?;
+    Ok(deleted > 0)
+}
@@ -0,0 +1,112 @@
+//! Rendering of hook mappings, see `config::HookMapping`.
+use serde_json::Value;
+
+use crate::config::HookMapping;
+use crate::errors::{Error, Result};
+use crate::preferences::Priority;
+
+/// Fields of a message extracted from a payload.
+#[derive(Debug, PartialEq)]
+pub struct Extracted {
+    pub title: String,
+    pub body: Option<String>,
+    pub url: Option<String>,
+    pub priority: Option<Priority>,
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Replace the `{{ <jsonpath> }}` placeholders of the template by the values
+/// they select in the payload, joined by `, ` if many.
+pub fn render(template: &str, payload: &Value) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| Error::BadRequest(format!("Unclosed placeholder in {}", template)))?;
+        let path = rest[start + 2..start + end].trim();
+        let selected = jsonpath_lib::select(payload, path)
+            .map_err(|_| Error::BadRequest(format!("Bad jsonpath {}", path)))?;
+        let values: Vec<String> = selected.into_iter().map(text).collect();
+        rendered.push_str(&values.join(", "));
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+fn render_optional(template: &Option<String>, payload: &Value) -> Result<Option<String>> {
+    let rendered = match template {
+        Some(template) => render(template, payload)?,
+        None => return Ok(None),
+    };
+    let rendered = rendered.trim();
+    Ok(match rendered.is_empty() {
+        true => None,
+        false => Some(rendered.to_owned()),
+    })
+}
+
+/// Check the placeholders of the mapping, e.g. before saving it.
+pub fn check(mapping: &HookMapping) -> Result<()> {
+    apply(mapping, &Value::Null).map(|_| ())
+}
+
+/// Extract a message from the payload, an unknown priority is left to the default.
+pub fn apply(mapping: &HookMapping, payload: &Value) -> Result<Extracted> {
+    let title = render(&mapping.title, payload)?.trim().to_owned();
+    let priority = render_optional(&mapping.priority, payload)?.and_then(|rendered| {
+        let name = mapping.priorities.get(&rendered).unwrap_or(&rendered);
+        Priority::parse(name)
+    });
+    Ok(Extracted {
+        title,
+        body: render_optional(&mapping.body, payload)?,
+        url: render_optional(&mapping.url, payload)?,
+        priority,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply() {
+        let payload = json!({
+            "state": "alerting",
+            "ruleName": "CPU",
+            "evalMatches": [{ "metric": "a", "value": 95 }, { "metric": "b", "value": 91 }],
+            "ruleUrl": null,
+        });
+        let mapping = HookMapping {
+            title: "[{{ $.state }}] {{$.ruleName}}".to_owned(),
+            body: Some("{{ $.evalMatches[*].value }}".to_owned()),
+            url: Some("{{ $.ruleUrl }}".to_owned()),
+            priority: Some("{{ $.state }}".to_owned()),
+            priorities: vec![("alerting".to_owned(), "high".to_owned())]
+                .into_iter()
+                .collect(),
+        };
+        assert_eq!(
+            apply(&mapping, &payload).unwrap(),
+            Extracted {
+                title: "[alerting] CPU".to_owned(),
+                body: Some("95, 91".to_owned()),
+                url: None,
+                priority: Some(Priority::High),
+            }
+        );
+        assert!(check(&mapping).is_ok());
+        assert!(render("{{ $.state", &payload).is_err());
+    }
+}
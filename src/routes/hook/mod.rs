@@ -0,0 +1,5 @@
+pub mod actions;
+mod mapping;
+mod routes;
+
+pub use routes::{configure, find_receiver};
@@ -0,0 +1,210 @@
+use crate::config::HookMapping;
+use crate::errors::{Error, Result};
+use crate::models::ReceiverHookMapping;
+use crate::rate_limit::{self, Bucket};
+use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+use crate::wechat::template_message::NewMessage;
+use actix_web::{web, HttpRequest, HttpResponse};
+use failure::ResultExt;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use super::mapping;
+
+/// max mappings saved by a receiver
+const MAX_MAPPINGS: i64 = 20;
+const MAX_NAME_LEN: usize = 64;
+
+/// The receiver of the send key, if saved along with their account.
+pub async fn find_receiver(state: &web::Data<AppState>, sendkey: &str) -> Result<String> {
+    let db_state = state.clone();
+    let hash = crate::sendkey::hash(sendkey);
+    let receiver = web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        crate::routes::message::actions::find_receiver_by_sendkey(&hash, &con)
+    })
+    .await?
+    .ok_or_else(|| Error::Unauthorized("Invalid sendkey".to_owned()))?;
+    // saved before the secret key changed
+    crate::sendkey::verify(&state.config.secret_key, &receiver, sendkey)?;
+    Ok(receiver)
+}
+
+/// POST /hook/{sendkey}/{mapping}, send the json posted by another tool as a message.
+///
+/// The mapping saved by the receiver is used, or the one in the config.
+async fn post_hook(
+    params: web::Path<(String, String)>,
+    body: web::Bytes,
+    state: web::Data<AppState>,
+    request: HttpRequest,
+) -> Result<HttpResponse> {
+    let (sendkey, name) = params.into_inner();
+    // the sender bucket is checked once the send key is valid, when sending the message
+    rate_limit::check_ip(&state, &request).await?;
+    let payload: Value = serde_json::from_slice(&body)
+        .map_err(|e| Error::BadRequest(format!("Bad json payload: {}", e)))?;
+
+    let receiver = find_receiver(&state, &sendkey).await?;
+    let db_state = state.clone();
+    let (owner, mapping_name) = (receiver.clone(), name.clone());
+    let saved = web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        super::actions::find_mapping(&owner, &mapping_name, &con)
+    })
+    .await?;
+    let mapping: HookMapping = match saved {
+        Some(saved) => serde_json::from_str(&saved.mapping).context("Bad saved hook mapping")?,
+        None => state
+            .config
+            .hook_mappings
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| Error::NotFound(format!("Unknown mapping {}", name)))?,
+    };
+
+    let extracted = mapping::apply(&mapping, &payload)?;
+    if extracted.title.is_empty() {
+        return Err(Error::BadRequest(
+            "The mapping rendered an empty title".to_owned(),
+        ));
+    }
+    let message = NewMessage {
+        receiver,
+        title: extracted.title,
+        body: extracted.body,
+        url: extracted.url,
+        priority: extracted.priority,
+        sendkey: Some(sendkey),
+        ..Default::default()
+    };
+    crate::routes::message::submit(message, state, &request).await
+}
+
+#[derive(Deserialize)]
+struct ReceiverQuery {
+    receiver: String,
+    sendkey: String,
+}
+
+/// Mappings saved by the receiver, and names of the ones in the config,
+/// authorised by the receiver's send key.
+async fn list_mappings(
+    query: web::Query<ReceiverQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &query.receiver, &query.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &query.sendkey).await?;
+    let receiver = query.receiver;
+    let db_state = state.clone();
+    let saved = web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        super::actions::list_mappings(&receiver, &con)
+    })
+    .await?;
+    let mut mappings = Map::new();
+    for saved in saved {
+        let mapping: Value =
+            serde_json::from_str(&saved.mapping).context("Bad saved hook mapping")?;
+        mappings.insert(saved.name, mapping);
+    }
+    let mut configured: Vec<&String> = state.config.hook_mappings.keys().collect();
+    configured.sort();
+    Ok(HttpResponse::Ok().json(json!({
+        "mappings": mappings,
+        "configured": configured,
+    })))
+}
+
+#[derive(Deserialize)]
+struct MappingForm {
+    receiver: String,
+    sendkey: String,
+    mapping: HookMapping,
+}
+
+/// Save a mapping of the receiver, authorised by the receiver's send key.
+async fn save_mapping(
+    params: web::Path<(String,)>,
+    form: web::Json<MappingForm>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let name = params.into_inner().0;
+    let form = form.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &form.receiver, &form.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &form.sendkey).await?;
+    let valid_name = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || name.len() > MAX_NAME_LEN || !name.chars().all(valid_name) {
+        return Err(Error::BadRequest(
+            "name must be letters, digits, - or _".to_owned(),
+        ));
+    }
+    mapping::check(&form.mapping)?;
+
+    let saved = ReceiverHookMapping {
+        receiver_id: form.receiver.clone(),
+        name: name.clone(),
+        mapping: serde_json::to_string(&form.mapping).unwrap(),
+        updated_time: unix_timestamp(),
+    };
+    let (receiver, sendkey) = (form.receiver, form.sendkey);
+    web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        use crate::routes::message::actions::set_receiver_sendkey;
+        let replacing = super::actions::find_mapping(&receiver, &name, &con)?.is_some();
+        if !replacing && super::actions::count_mappings(&receiver, &con)? >= MAX_MAPPINGS {
+            return Err(Error::Conflict(format!(
+                "At most {} mappings can be saved",
+                MAX_MAPPINGS
+            )));
+        }
+        super::actions::save_mapping(&saved, &con)?;
+        // for receivers subscribed before send keys were saved
+        set_receiver_sendkey(&receiver, &sendkey, &con)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({ "mapping": form.mapping })))
+}
+
+/// Delete a mapping of the receiver, authorised by the receiver's send key.
+async fn delete_mapping(
+    params: web::Path<(String,)>,
+    query: web::Query<ReceiverQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let name = params.into_inner().0;
+    let query = query.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &query.receiver, &query.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &query.sendkey).await?;
+    let receiver = query.receiver;
+    let deleted = web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        super::actions::delete_mapping(&receiver, &name, &con)
+    })
+    .await?;
+    match deleted {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Err(Error::NotFound("Mapping not found".to_owned())),
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/hook/{sendkey}/{mapping}")
+            .name("post json by mapping")
+            .route(web::post().to(post_hook)),
+    )
+    .service(
+        web::resource("/preferences/hooks")
+            .name("hook mappings of receiver")
+            .route(web::get().to(list_mappings)),
+    )
+    .service(
+        web::resource("/preferences/hooks/{name}")
+            .name("hook mapping of receiver")
+            .route(web::put().to(save_mapping))
+            .route(web::delete().to(delete_mapping)),
+    );
+}
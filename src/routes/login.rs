@@ -0,0 +1,77 @@
+use actix_web::{web, HttpResponse};
+use redis::AsyncCommands;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::errors::{Error, Result};
+use crate::login_token::LoginToken;
+use crate::sendkey;
+use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+
+const LOGIN_TOKEN_TTL_SECONDS: u64 = 60;
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/login/exchange")
+            .name("Exchange login token")
+            .route(web::post().to(exchange_login_token)),
+    );
+}
+
+fn nonce_key(nonce: &str) -> String {
+    format!("wxpush:login:{}", nonce)
+}
+
+/// Issue a one-time login token for the open id.
+pub async fn issue_login_token(state: &AppState, open_id: String) -> Result<Value> {
+    let token = LoginToken::new(open_id, LOGIN_TOKEN_TTL_SECONDS);
+    let mut con = state.redis_connection().await?;
+    let _: () = con
+        .set_ex(nonce_key(&token.nonce), 1, LOGIN_TOKEN_TTL_SECONDS as usize)
+        .await?;
+    Ok(json!({
+        "login_token": token.encode(&state.config.secret_key),
+        "expires_in": LOGIN_TOKEN_TTL_SECONDS,
+    }))
+}
+
+#[derive(Deserialize)]
+struct ExchangeForm {
+    login_token: String,
+}
+
+/// Exchange a login token for the open id and send key of the user, each token can be exchanged only once.
+async fn exchange_login_token(
+    form: web::Form<ExchangeForm>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let unauthorized = || Error::Unauthorized("Invalid or expired login token".to_owned());
+    let token =
+        LoginToken::decode(&form.login_token, &state.config.secret_key).ok_or_else(unauthorized)?;
+    // consume the nonce
+    let mut con = state.redis_connection().await?;
+    let deleted: i32 = con.del(nonce_key(&token.nonce)).await?;
+    if deleted == 0 {
+        return Err(unauthorized());
+    }
+    log::info!("Login token exchanged");
+    // to find the receiver by it at `/hook/{sendkey}/{mapping}`
+    let key = sendkey::sendkey(&state.config.secret_key, &token.open_id);
+    let (receiver, hash) = (token.open_id.clone(), sendkey::hash(&key));
+    let db_state = state.clone();
+    web::block(move || {
+        let con = db_state.db_pool.get()?;
+        crate::routes::message::actions::save_receiver_sendkey(
+            &receiver,
+            &hash,
+            unix_timestamp(),
+            &con,
+        )
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "sendkey": key,
+        "openID": token.open_id,
+    })))
+}
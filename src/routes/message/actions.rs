@@ -1,5 +1,8 @@
+use crate::config::DEFAULT_ACCOUNT;
+use crate::delivery::ChannelKind;
 use crate::errors::Result;
 use crate::models;
+use crate::utils::unix_timestamp;
 use uuid::Uuid;
 
 use diesel::prelude::*;
@@ -10,6 +13,356 @@ pub fn insert_message(msg: &models::Message, con: &PgConnection) -> Result<()> {
     Ok(())
 }
 
+/// Messages of the receiver, newest first, strictly before the (created_time, id) cursor if any.
+pub fn find_messages_by_receiver(
+    receiver: &str,
+    before: Option<(i64, Uuid)>,
+    count: i64,
+    con: &PgConnection,
+) -> Result<Vec<models::Message>> {
+    use crate::schema::messages::dsl::*;
+    let mut query = messages
+        .filter(receiver_id.eq(receiver))
+        .filter(revoked_time.is_null())
+        .filter(expires_at.is_null().or(expires_at.gt(unix_timestamp())))
+        .into_boxed();
+    if let Some((time, uuid)) = before {
+        query = query.filter(
+            created_time
+                .lt(time)
+                .or(created_time.eq(time).and(id.lt(uuid))),
+        );
+    }
+    let msgs = query
+        .order((created_time.desc(), id.desc()))
+        .limit(count)
+        .load::<models::Message>(con)?;
+    Ok(msgs)
+}
+
+/// Which senders a retention rule applies to, by the receiver whose send key they used.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SenderScope {
+    Only(String),
+    /// all senders except those with their own rules, including messages sent without a send key
+    Except(Vec<String>),
+}
+
+/// Delete at most `count` messages created before `time`, returns ids of the deleted.
+pub fn delete_messages_before(
+    scope: &SenderScope,
+    time: i64,
+    count: i64,
+    con: &PgConnection,
+) -> Result<Vec<Uuid>> {
+    use crate::schema::messages::dsl::*;
+    let mut query = messages
+        .select(id)
+        .filter(created_time.lt(time))
+        .into_boxed();
+    query = match scope {
+        SenderScope::Only(sender) => query.filter(sender_id.eq(sender.clone())),
+        SenderScope::Except(senders) => {
+            query.filter(sender_id.is_null().or(sender_id.ne_all(senders.clone())))
+        }
+    };
+    let ids = query.limit(count).load::<Uuid>(con)?;
+    diesel::delete(messages.filter(id.eq_any(&ids))).execute(con)?;
+    Ok(ids)
+}
+
+/// Blank sender IP and UA of at most `count` messages created before `time`, returns the number updated.
+pub fn anonymise_messages_before(
+    scope: &SenderScope,
+    time: i64,
+    count: i64,
+    con: &PgConnection,
+) -> Result<usize> {
+    use crate::schema::messages::dsl::*;
+    let mut query = messages
+        .select(id)
+        .filter(created_time.lt(time))
+        .filter(ip.ne("").or(UA.ne("")))
+        .into_boxed();
+    query = match scope {
+        SenderScope::Only(sender) => query.filter(sender_id.eq(sender.clone())),
+        SenderScope::Except(senders) => {
+            query.filter(sender_id.is_null().or(sender_id.ne_all(senders.clone())))
+        }
+    };
+    let ids = query.limit(count).load::<Uuid>(con)?;
+    let updated = diesel::update(messages.filter(id.eq_any(&ids)))
+        .set((ip.eq(""), UA.eq("")))
+        .execute(con)?;
+    Ok(updated)
+}
+
+/// Marks around matched words in search results, replaced after html escaping.
+///
+/// They are stripped from the text before highlighting, so the ones in results are all ts_headline's.
+pub const HIGHLIGHT_START: char = '\u{2}';
+pub const HIGHLIGHT_STOP: char = '\u{3}';
+
+/// Full-text search over title and body of the receiver's messages, best match first.
+///
+/// The tsvector expression must match the one of `messages_search_idx` for the index to be used.
+pub fn search_messages(
+    receiver: &str,
+    keywords: &str,
+    count: i64,
+    con: &PgConnection,
+) -> Result<Vec<models::SearchResult>> {
+    use diesel::sql_types::{BigInt, Text};
+    let options = format!(
+        "StartSel={}, StopSel={}, MaxFragments=2, MaxWords=20, MinWords=5",
+        HIGHLIGHT_START, HIGHLIGHT_STOP
+    );
+    let results = diesel::sql_query(
+        "SELECT id, created_time, \
+            ts_headline('pg_catalog.simple', translate(title, $6, ''), query, $3) AS title, \
+            ts_headline('pg_catalog.simple', translate(body, $6, ''), query, $3) AS snippet \
+        FROM messages, plainto_tsquery('pg_catalog.simple', $2) query \
+        WHERE receiver_id = $1 \
+            AND to_tsvector('pg_catalog.simple', title || ' ' || coalesce(body, '')) @@ query \
+            AND revoked_time IS NULL AND (expires_at IS NULL OR expires_at > $5) \
+        ORDER BY ts_rank(to_tsvector('pg_catalog.simple', title || ' ' || coalesce(body, '')), query) DESC, \
+            created_time DESC \
+        LIMIT $4",
+    )
+    .bind::<Text, _>(receiver)
+    .bind::<Text, _>(keywords)
+    .bind::<Text, _>(options)
+    .bind::<BigInt, _>(count)
+    .bind::<BigInt, _>(unix_timestamp())
+    .bind::<Text, _>(format!("{}{}", HIGHLIGHT_START, HIGHLIGHT_STOP))
+    .load(con)?;
+    Ok(results)
+}
+
+/// Mark the message as revoked and erase its content.
+pub fn revoke_message(uuid: Uuid, time: i64, con: &PgConnection) -> Result<usize> {
+    use crate::schema::messages::dsl::*;
+    let updated = diesel::update(messages.filter(id.eq(uuid)))
+        .set((
+            revoked_time.eq(time),
+            title.eq(""),
+            body.eq(""),
+            url.eq(None::<String>),
+        ))
+        .execute(con)?;
+    Ok(updated)
+}
+
+/// Count a duplicate of the message if its dedup window is open, returns the new count.
+pub fn count_duplicate(uuid: Uuid, con: &PgConnection) -> Result<Option<i32>> {
+    use crate::schema::messages::dsl::*;
+    let count = diesel::update(messages.filter(id.eq(uuid)).filter(dedup_closed.eq(false)))
+        .set(duplicates.eq(duplicates + 1))
+        .returning(duplicates)
+        .get_result(con)
+        .optional()?;
+    Ok(count)
+}
+
+/// Close at most `count` dedup windows over at `now`, returns their messages.
+pub fn close_dedup_windows(
+    now: i64,
+    count: i64,
+    con: &PgConnection,
+) -> Result<Vec<models::Message>> {
+    use crate::schema::messages::dsl::*;
+    con.transaction(|| {
+        let closed = messages
+            .filter(dedup_key.is_not_null())
+            .filter(dedup_closed.eq(false))
+            .filter(dedup_until.le(now))
+            .order(dedup_until)
+            .limit(count)
+            .for_update()
+            .skip_locked()
+            .load::<models::Message>(con)?;
+        let ids: Vec<Uuid> = closed.iter().map(|m| m.id).collect();
+        diesel::update(messages.filter(id.eq_any(ids)))
+            .set(dedup_closed.eq(true))
+            .execute(con)?;
+        Ok(closed)
+    })
+}
+
+/// Take at most `count` queued messages, oldest first, except those of the app ids,
+/// and lease them for `lease` seconds to push them.
+pub fn claim_queued_messages(
+    now: i64,
+    lease: i64,
+    count: i64,
+    excluded_app_ids: &[String],
+    con: &PgConnection,
+) -> Result<Vec<models::Message>> {
+    use crate::schema::messages::dsl::*;
+    con.transaction(|| {
+        let claimed = messages
+            .filter(queued.eq(true))
+            .filter(queue_lease.is_null().or(queue_lease.le(now)))
+            .filter(app_id.ne_all(excluded_app_ids))
+            .order(created_time)
+            .limit(count)
+            .for_update()
+            .skip_locked()
+            .load::<models::Message>(con)?;
+        let ids: Vec<Uuid> = claimed.iter().map(|m| m.id).collect();
+        diesel::update(messages.filter(id.eq_any(ids)))
+            .set(queue_lease.eq(now + lease))
+            .execute(con)?;
+        Ok(claimed)
+    })
+}
+
+/// Save the result of pushing a queued message, ending its lease.
+pub fn update_queued_message(
+    uuid: Uuid,
+    still_queued: bool,
+    msg_id: Option<i64>,
+    sent_by: Option<ChannelKind>,
+    con: &PgConnection,
+) -> Result<()> {
+    use crate::schema::messages::dsl::*;
+    diesel::update(messages.filter(id.eq(uuid)))
+        .set((
+            queued.eq(still_queued),
+            queue_lease.eq(None::<i64>),
+            wechat_msg_id.eq(msg_id),
+            channel.eq(sent_by.map(ChannelKind::as_str)),
+        ))
+        .execute(con)?;
+    Ok(())
+}
+
+/// Take at most `count` messages held till `now`, ordered by receiver and app id,
+/// and hold them for `lease` more seconds.
+pub fn claim_held_messages(
+    now: i64,
+    lease: i64,
+    count: i64,
+    con: &PgConnection,
+) -> Result<Vec<models::Message>> {
+    use crate::schema::messages::dsl::*;
+    con.transaction(|| {
+        let claimed = messages
+            .filter(held_until.le(now))
+            .order((receiver_id, app_id, created_time))
+            .limit(count)
+            .for_update()
+            .skip_locked()
+            .load::<models::Message>(con)?;
+        let ids: Vec<Uuid> = claimed.iter().map(|m| m.id).collect();
+        diesel::update(messages.filter(id.eq_any(ids)))
+            .set(held_until.eq(now + lease))
+            .execute(con)?;
+        Ok(claimed)
+    })
+}
+
+/// Stop holding the messages, as they are pushed or gone.
+pub fn release_held_messages(
+    ids: &[Uuid],
+    msg_id: Option<i64>,
+    sent_by: Option<ChannelKind>,
+    con: &PgConnection,
+) -> Result<()> {
+    use crate::schema::messages::dsl::*;
+    diesel::update(messages.filter(id.eq_any(ids)))
+        .set((
+            held_until.eq(None::<i64>),
+            wechat_msg_id.eq(msg_id),
+            channel.eq(sent_by.map(ChannelKind::as_str)),
+        ))
+        .execute(con)?;
+    Ok(())
+}
+
+/// Save a digest pushed for the held messages.
+pub fn insert_digest(
+    digest: &models::Digest,
+    ids: &[Uuid],
+    sent_by: Option<ChannelKind>,
+    con: &PgConnection,
+) -> Result<()> {
+    con.transaction(|| {
+        diesel::insert_into(crate::schema::digests::table)
+            .values(digest)
+            .execute(con)?;
+        use crate::schema::messages::dsl::*;
+        diesel::update(messages.filter(id.eq_any(ids)))
+            .set((
+                held_until.eq(None::<i64>),
+                digest_id.eq(digest.id),
+                channel.eq(sent_by.map(ChannelKind::as_str)),
+            ))
+            .execute(con)?;
+        Ok(())
+    })
+}
+
+/// Messages pushed in the digest, oldest first.
+pub fn find_digest_messages(digest: Uuid, con: &PgConnection) -> Result<Vec<models::Message>> {
+    use crate::schema::messages::dsl::*;
+    let found = messages
+        .filter(digest_id.eq(digest))
+        .order(created_time)
+        .load::<models::Message>(con)?;
+    Ok(found)
+}
+
+/// Record the first acknowledgement of a message, returns 0 if already acknowledged or revoked.
+pub fn acknowledge_message(uuid: Uuid, by: &str, time: i64, con: &PgConnection) -> Result<usize> {
+    use crate::schema::messages::dsl::*;
+    let updated = diesel::update(
+        messages
+            .filter(id.eq(uuid))
+            .filter(acked_time.is_null())
+            .filter(revoked_time.is_null())
+            .filter(expires_at.is_null().or(expires_at.gt(time))),
+    )
+    .set((acked_time.eq(time), acked_by.eq(by)))
+    .execute(con)?;
+    Ok(updated)
+}
+
+pub fn insert_view(view: &models::NewMessageView, con: &PgConnection) -> Result<()> {
+    use crate::schema::message_views::dsl::*;
+    diesel::insert_into(message_views)
+        .values(view)
+        .execute(con)?;
+    Ok(())
+}
+
+/// View statistics of a message, by client.
+pub fn find_view_stats(uuid: Uuid, con: &PgConnection) -> Result<Vec<models::ViewStats>> {
+    let stats = diesel::sql_query(
+        "SELECT client, COUNT(*) AS count, \
+            MIN(viewed_time) AS first_viewed_time, MAX(viewed_time) AS last_viewed_time \
+        FROM message_views WHERE message_id = $1 GROUP BY client",
+    )
+    .bind::<diesel::sql_types::Uuid, _>(uuid)
+    .load(con)?;
+    Ok(stats)
+}
+
+/// The message pushed by the app with the msgid, as msgids are per app.
+pub fn find_message_by_wechat_msg_id(
+    app: &str,
+    msg_id: i64,
+    con: &PgConnection,
+) -> Result<Option<models::Message>> {
+    use crate::schema::messages::dsl::*;
+    let mut msgs = messages
+        .filter(app_id.eq(app))
+        .filter(wechat_msg_id.eq(msg_id))
+        .limit(1)
+        .load::<models::Message>(con)?;
+    Ok(msgs.pop())
+}
+
 pub fn find_message_by_uuid(uuid: Uuid, con: &PgConnection) -> Result<Option<models::Message>> {
     use crate::schema::messages::dsl::*;
     let mut msgs = messages
@@ -18,3 +371,164 @@ pub fn find_message_by_uuid(uuid: Uuid, con: &PgConnection) -> Result<Option<mod
         .load::<models::Message>(&*con)?;
     Ok(msgs.pop())
 }
+
+/// Name of the official account the receiver subscribed to, if recorded.
+pub fn find_receiver_account(receiver: &str, con: &PgConnection) -> Result<Option<String>> {
+    use crate::schema::receiver_accounts::dsl::*;
+    let mut found = receiver_accounts
+        .filter(receiver_id.eq(receiver))
+        .select(account)
+        .limit(1)
+        .load::<String>(con)?;
+    Ok(found.pop())
+}
+
+/// The receiver whose send key hash is saved along with their account.
+pub fn find_receiver_by_sendkey(hash: &str, con: &PgConnection) -> Result<Option<String>> {
+    use crate::schema::receiver_accounts::dsl::*;
+    let mut found = receiver_accounts
+        .filter(sendkey.eq(hash))
+        .select(receiver_id)
+        .limit(1)
+        .load::<String>(con)?;
+    Ok(found.pop())
+}
+
+/// Save the send key hash of the receiver if their account is saved.
+pub fn set_receiver_sendkey(receiver: &str, hash: &str, con: &PgConnection) -> Result<()> {
+    use crate::schema::receiver_accounts::dsl::*;
+    diesel::update(receiver_accounts.filter(receiver_id.eq(receiver)))
+        .set(sendkey.eq(hash))
+        .execute(con)?;
+    Ok(())
+}
+
+/// Save the send key hash of the receiver, recording the default account if none is saved.
+pub fn save_receiver_sendkey(
+    receiver: &str,
+    hash: &str,
+    now: i64,
+    con: &PgConnection,
+) -> Result<()> {
+    use crate::schema::receiver_accounts::dsl::*;
+    let receiver_account = models::ReceiverAccount {
+        receiver_id: receiver.to_owned(),
+        account: DEFAULT_ACCOUNT.to_owned(),
+        updated_time: now,
+        sendkey: Some(hash.to_owned()),
+    };
+    diesel::insert_into(receiver_accounts)
+        .values(&receiver_account)
+        .on_conflict(receiver_id)
+        .do_update()
+        .set(sendkey.eq(hash))
+        .execute(con)?;
+    Ok(())
+}
+
+/// At most `count` receivers whose send key hash is not saved yet.
+pub fn find_receivers_without_sendkey(count: i64, con: &PgConnection) -> Result<Vec<String>> {
+    use crate::schema::receiver_accounts::dsl::*;
+    let found = receiver_accounts
+        .filter(sendkey.is_null())
+        .select(receiver_id)
+        .limit(count)
+        .load::<String>(con)?;
+    Ok(found)
+}
+
+pub fn save_receiver_account(
+    receiver_account: &models::ReceiverAccount,
+    con: &PgConnection,
+) -> Result<()> {
+    use crate::schema::receiver_accounts::dsl::*;
+    diesel::insert_into(receiver_accounts)
+        .values(receiver_account)
+        .on_conflict(receiver_id)
+        .do_update()
+        .set(receiver_account)
+        .execute(con)?;
+    Ok(())
+}
+
+pub fn insert_escalation(escalation: &models::NewEscalation, con: &PgConnection) -> Result<()> {
+    use crate::schema::escalations::dsl::*;
+    diesel::insert_into(escalations)
+        .values(escalation)
+        .execute(con)?;
+    Ok(())
+}
+
+/// Claim at most `count` pending escalations due at `now`.
+///
+/// Claimed escalations are leased by postponing them for `lease` seconds, so that
+/// they are not picked up again by another poller while being handled.
+pub fn claim_due_escalations(
+    now: i64,
+    lease: i64,
+    count: i64,
+    con: &PgConnection,
+) -> Result<Vec<models::Escalation>> {
+    use crate::schema::escalations::dsl::*;
+    con.transaction(|| {
+        let due = escalations
+            .filter(status.eq("pending"))
+            .filter(next_time.le(now))
+            .order(next_time)
+            .limit(count)
+            .for_update()
+            .skip_locked()
+            .load::<models::Escalation>(con)?;
+        let ids: Vec<i64> = due.iter().map(|e| e.id).collect();
+        diesel::update(escalations.filter(id.eq_any(ids)))
+            .set(next_time.eq(now + lease))
+            .execute(con)?;
+        Ok(due)
+    })
+}
+
+/// Save the progress of an escalation.
+pub fn update_escalation(escalation: &models::Escalation, con: &PgConnection) -> Result<()> {
+    use crate::schema::escalations::dsl::*;
+    diesel::update(escalations.filter(id.eq(escalation.id)))
+        .set((
+            step.eq(escalation.step),
+            next_time.eq(escalation.next_time),
+            status.eq(&escalation.status),
+        ))
+        .execute(con)?;
+    Ok(())
+}
+
+pub fn cancel_escalations(uuid: Uuid, con: &PgConnection) -> Result<usize> {
+    use crate::schema::escalations::dsl::*;
+    let updated = diesel::update(
+        escalations
+            .filter(message_id.eq(uuid))
+            .filter(status.eq("pending")),
+    )
+    .set(status.eq("cancelled"))
+    .execute(con)?;
+    Ok(updated)
+}
+
+pub fn find_escalation_by_message(
+    uuid: Uuid,
+    con: &PgConnection,
+) -> Result<Option<models::Escalation>> {
+    use crate::schema::escalations::dsl::*;
+    let mut found = escalations
+        .filter(message_id.eq(uuid))
+        .limit(1)
+        .load::<models::Escalation>(con)?;
+    Ok(found.pop())
+}
+
+pub fn count_views(uuid: Uuid, con: &PgConnection) -> Result<i64> {
+    use crate::schema::message_views::dsl::*;
+    let count = message_views
+        .filter(message_id.eq(uuid))
+        .count()
+        .get_result(con)?;
+    Ok(count)
+}
@@ -0,0 +1,53 @@
+//! Deduplication of identical messages by `dedup_key`.
+//!
+//! The first message with a key opens a window, saved in redis along with its
+//! token. Messages with the same key within the window are counted on the
+//! first one instead of being pushed, and a follow-up with the count is pushed
+//! when the window closes, see `jobs::dedup`.
+use redis::aio::Connection;
+use redis::AsyncCommands;
+use uuid::Uuid;
+
+use crate::errors::Result;
+
+pub const DEFAULT_WINDOW_MINUTES: u64 = 10;
+pub const MAX_WINDOW_MINUTES: u64 = 24 * 60;
+pub const MAX_KEY_LEN: usize = 255;
+
+fn redis_key(receiver: &str, key: &str) -> String {
+    format!("wxpush:dedup:{}:{}", receiver, key)
+}
+
+/// Open a window with the message, or return the first message of the open window.
+pub async fn join(
+    con: &mut Connection,
+    receiver: &str,
+    key: &str,
+    id: Uuid,
+    window_seconds: usize,
+) -> Result<Option<Uuid>> {
+    let redis_key = redis_key(receiver, key);
+    let opened: bool = redis::cmd("SET")
+        .arg(&redis_key)
+        .arg(id.to_string())
+        .arg("NX")
+        .arg("EX")
+        .arg(window_seconds)
+        .query_async(con)
+        .await?;
+    if opened {
+        return Ok(None);
+    }
+    let first: Option<String> = con.get(&redis_key).await?;
+    Ok(first.and_then(|s| s.parse().ok()))
+}
+
+/// Close the window opened by the message, e.g. as it failed to send.
+pub async fn leave(con: &mut Connection, receiver: &str, key: &str, id: Uuid) -> Result<()> {
+    let redis_key = redis_key(receiver, key);
+    let first: Option<String> = con.get(&redis_key).await?;
+    if first == Some(id.to_string()) {
+        let _: () = con.del(&redis_key).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! Idempotency keys make retries of `POST /message` safe.
+//!
+//! The response of a request is saved in redis under its key, scoped by the
+//! receiver, and replayed for later requests with the same key. Along with it
+//! is a fingerprint of the message, so that a key reused for another message
+//! is refused instead of replaying the response of the first one.
+use redis::aio::Connection;
+use redis::AsyncCommands;
+use serde_json::{json, Value};
+
+use crate::errors::Result;
+use crate::utils::hmac_sha256_hex;
+use crate::wechat::template_message::NewMessage;
+
+pub const HEADER: &str = "Idempotency-Key";
+pub const MAX_KEY_LEN: usize = 255;
+/// How long a response is kept for replay.
+const TTL_SECONDS: usize = 24 * 60 * 60;
+/// Release the key if the request never finishes, e.g. the server stopped.
+const PENDING_TTL_SECONDS: usize = 60;
+
+pub enum Claim {
+    /// first request with the key, go ahead
+    New,
+    /// the response to replay
+    Done(Value),
+    /// another request with the key is not finished yet
+    InProgress,
+    /// the key was used for another message
+    Mismatch,
+}
+
+fn redis_key(receiver: &str, key: &str) -> String {
+    format!("wxpush:idempotency:{}:{}", receiver, key)
+}
+
+/// Fingerprint of the message, without its idempotency key.
+pub fn fingerprint(key: &str, message: &NewMessage) -> String {
+    let message = NewMessage {
+        idempotency_key: None,
+        ..message.clone()
+    };
+    let data = serde_json::to_string(&message).unwrap_or_default();
+    hmac_sha256_hex(key, &data)
+}
+
+pub async fn claim(
+    con: &mut Connection,
+    receiver: &str,
+    key: &str,
+    fingerprint: &str,
+) -> Result<Claim> {
+    let redis_key = redis_key(receiver, key);
+    let pending = json!({ "fingerprint": fingerprint });
+    let claimed: bool = redis::cmd("SET")
+        .arg(&redis_key)
+        .arg(pending.to_string())
+        .arg("NX")
+        .arg("EX")
+        .arg(PENDING_TTL_SECONDS)
+        .query_async(con)
+        .await?;
+    if claimed {
+        return Ok(Claim::New);
+    }
+    let saved: Option<String> = con.get(&redis_key).await?;
+    let saved = match saved.and_then(|s| s.parse::<Value>().ok()) {
+        Some(saved) => saved,
+        // expired just now
+        None => return Ok(Claim::InProgress),
+    };
+    if saved["fingerprint"].as_str() != Some(fingerprint) {
+        return Ok(Claim::Mismatch);
+    }
+    Ok(match saved.get("response") {
+        Some(response) => Claim::Done(response.clone()),
+        None => Claim::InProgress,
+    })
+}
+
+/// Save the response to replay, once the message went out.
+pub async fn complete(
+    con: &mut Connection,
+    receiver: &str,
+    key: &str,
+    fingerprint: &str,
+    response: &Value,
+) -> Result<()> {
+    let saved = json!({ "fingerprint": fingerprint, "response": response });
+    let _: () = con
+        .set_ex(redis_key(receiver, key), saved.to_string(), TTL_SECONDS)
+        .await?;
+    Ok(())
+}
+
+/// Release the key of a request that failed before anything went out, to let the client retry.
+pub async fn release(con: &mut Connection, receiver: &str, key: &str) -> Result<()> {
+    let _: () = con.del(redis_key(receiver, key)).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        let message = NewMessage {
+            receiver: "open_id".to_owned(),
+            title: "title".to_owned(),
+            ..NewMessage::default()
+        };
+        let with_key = NewMessage {
+            idempotency_key: Some("key".to_owned()),
+            ..message.clone()
+        };
+        let other = NewMessage {
+            title: "other".to_owned(),
+            ..message.clone()
+        };
+        assert_eq!(fingerprint("key", &message), fingerprint("key", &with_key));
+        assert_ne!(fingerprint("key", &message), fingerprint("key", &other));
+        assert_ne!(fingerprint("key", &message), fingerprint("other", &message));
+    }
+}
@@ -1,5 +1,8 @@
 mod routes;
 
-pub use routes::configure;
+pub use routes::{configure, detail_url, digest_url, redis_key, send, submit};
 
-mod actions;
+pub mod actions;
+mod dedup;
+mod idempotency;
+mod pages;
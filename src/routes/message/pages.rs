@@ -0,0 +1,133 @@
+use chrono::{Local, TimeZone};
+use serde_json::Value;
+
+use crate::html::body::{render_html, BodyFormat};
+use crate::html::{is_safe_url, Template};
+
+const MESSAGE: &str = include_str!("../../html/templates/message.html");
+const NOTICE: &str = include_str!("../../html/templates/notice.html");
+const ACK_FORM: &str = include_str!("../../html/templates/ack_form.html");
+const DIGEST: &str = include_str!("../../html/templates/digest.html");
+
+/// The acknowledge button, for a receiver with a valid ack token.
+pub struct AckForm<'a> {
+    pub action: &'a str,
+    pub by: &'a str,
+    pub ack: &'a str,
+}
+
+fn format_time(timestamp: i64) -> String {
+    Local
+        .timestamp(timestamp, 0)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+/// Render the detail page of a simplified message.
+pub fn message_page(message: &Value, ack_form: Option<AckForm>) -> String {
+    let title = message["title"].as_str().unwrap_or_default();
+    let body = message["body"].as_str().unwrap_or_default();
+    let format = BodyFormat::from_str_lossy(message["format"].as_str().unwrap_or_default());
+    let link = match message["url"].as_str() {
+        Some(url) if is_safe_url(url) => {
+            Template::new(r#"<a class="button" href="{{url}}">查看详情</a>"#)
+                .text("url", url)
+                .render()
+        }
+        _ => String::new(),
+    };
+    let ack = match (message["acked_time"].as_i64(), ack_form) {
+        (Some(acked_time), _) => Template::new(r#"<p class="notice">已于 {{time}} 确认</p>"#)
+            .text("time", &format_time(acked_time))
+            .render(),
+        (None, Some(form)) => Template::new(ACK_FORM)
+            .text("action", form.action)
+            .text("by", form.by)
+            .text("ack", form.ack)
+            .render(),
+        (None, None) => String::new(),
+    };
+    let duplicates = match (
+        message["duplicates"].as_i64(),
+        message["dedup_window"].as_i64(),
+    ) {
+        (Some(duplicates), Some(window)) if duplicates > 0 => {
+            Template::new(r#"<div class="time">{{minutes}} 分钟内共 {{count}} 次</div>"#)
+                .text("minutes", &(window / 60).to_string())
+                .text("count", &(duplicates + 1).to_string())
+                .render()
+        }
+        _ => String::new(),
+    };
+    Template::new(MESSAGE)
+        .text("title", title)
+        .text(
+            "time",
+            &format_time(message["created_time"].as_i64().unwrap_or(0)),
+        )
+        .text(
+            "body_class",
+            match format {
+                BodyFormat::Text => "body",
+                _ => "body rich",
+            },
+        )
+        .html("body", &render_html(body, format))
+        .html("link", &link)
+        .html("ack", &ack)
+        .html("duplicates", &duplicates)
+        .page(title)
+}
+
+/// A message listed in a digest.
+pub struct DigestItem<'a> {
+    pub title: &'a str,
+    pub created_time: i64,
+    pub url: &'a str,
+}
+
+/// Render the page of a digest, listing its messages.
+pub fn digest_page(created_time: i64, items: &[DigestItem]) -> String {
+    let title = format!("{} 条消息汇总", items.len());
+    let items: String = items
+        .iter()
+        .map(|item| {
+            Template::new(
+                r#"<li><a href="{{url}}">{{title}}</a><div class="time">{{time}}</div></li>"#,
+            )
+            .text("url", item.url)
+            .text("title", item.title)
+            .text("time", &format_time(item.created_time))
+            .render()
+        })
+        .collect();
+    Template::new(DIGEST)
+        .text("title", &title)
+        .text("time", &format_time(created_time))
+        .html("items", &items)
+        .page(&title)
+}
+
+pub fn acked_page(acked_time: i64) -> String {
+    Template::new(NOTICE)
+        .text("title", "已确认")
+        .text(
+            "notice",
+            &format!("该消息已于 {} 确认。", format_time(acked_time)),
+        )
+        .page("已确认")
+}
+
+pub fn gone_page() -> String {
+    Template::new(NOTICE)
+        .text("title", "消息已失效")
+        .text("notice", "该消息已过期或已被撤回。")
+        .page("消息已失效")
+}
+
+pub fn not_found_page() -> String {
+    Template::new(NOTICE)
+        .text("title", "消息不存在")
+        .text("notice", "该消息不存在或已被删除。")
+        .page("消息不存在")
+}
@@ -1,34 +1,92 @@
+use super::dedup;
+use super::idempotency::{self, Claim};
+use super::pages::{self, AckForm, DigestItem};
+use crate::ack_token;
+use crate::config::{Config, DEFAULT_ACCOUNT};
+use crate::delivery::{self, Delivery, DeliveryError};
 use crate::errors::{Error, Result};
-use crate::models::Message;
+use crate::html::body::{summarize, SUMMARY_MAX_CHARS};
+use crate::models::{Message, NewEscalation, NewMessageView};
+use crate::preferences::{self, Priority};
+use crate::rate_limit::{self, Bucket};
 use crate::shared_state::AppState;
-use crate::wechat::template_message::{apis, NewMessage};
+use crate::utils::unix_timestamp;
+use crate::webhook::{self, Event};
+use crate::wechat::template_message::NewMessage;
 use actix_web::{web, HttpRequest, HttpResponse};
 use redis::AsyncCommands;
+use serde::Deserialize;
 use serde_json::{json, Value};
 use std::time::SystemTime;
 use uuid::Uuid;
 
 const HIT_CACHE_SECONDS: usize = 5 * 60;
 const MISS_CACHE_SECONDS: usize = 10;
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+const MAX_ESCALATION_STEPS: i32 = 10;
 
 fn simplify_message(message: Message) -> Value {
     json!({
         "title": message.title,
         "body": message.body,
         "url": message.url,
-        "created_time": message.created_time
+        "format": message.format,
+        "created_time": message.created_time,
+        "expires_at": message.expires_at,
+        "revoked_time": message.revoked_time,
+        "acked_time": message.acked_time,
+        "duplicates": message.duplicates,
+        "priority": message.priority,
+        "held_until": message.held_until,
+        "dedup_window": message.dedup_until.map(|until| until - message.created_time),
     })
 }
 
-fn redis_key(uuid: &Uuid) -> String {
+/// The detail page of a message pushed by the account for one of its receivers, with their ack token.
+pub fn detail_url(config: &Config, account: &str, id: &Uuid, receiver: &str) -> String {
+    let base = &config.account(account).unwrap_or(&config.wechat).detail_url;
+    let by: String = url::form_urlencoded::byte_serialize(receiver.as_bytes()).collect();
+    format!(
+        "{}/{}?by={}&ack={}",
+        base,
+        id,
+        by,
+        ack_token::ack_token(&config.secret_key, id, receiver)
+    )
+}
+
+/// The page listing messages pushed in a digest, served by the server itself.
+pub fn digest_url(config: &Config, id: &Uuid) -> String {
+    format!("{}/digest/{}", config.public_url, id)
+}
+
+pub fn redis_key(uuid: &Uuid) -> String {
     format!("wxpush:msg:{}", uuid)
 }
 
-async fn message_detail(
-    params: web::Path<(Uuid,)>,
-    state: web::Data<AppState>,
-) -> Result<HttpResponse> {
-    let uuid = params.0;
+/// Cache no longer than the message lives.
+fn cache_seconds(message: &Value) -> usize {
+    match message["expires_at"].as_i64() {
+        Some(expires_at) => {
+            (expires_at - unix_timestamp()).clamp(1, HIT_CACHE_SECONDS as i64) as usize
+        }
+        None => HIT_CACHE_SECONDS,
+    }
+}
+
+/// Whether the simplified message is revoked or expired.
+fn is_gone(message: &Value) -> bool {
+    let revoked = !message["revoked_time"].is_null();
+    let expired = message["expires_at"]
+        .as_i64()
+        .map(|expires_at| expires_at <= unix_timestamp())
+        .unwrap_or(false);
+    revoked || expired
+}
+
+/// Load the simplified message, from redis cache if possible.
+async fn load_message(uuid: Uuid, state: web::Data<AppState>) -> Result<Option<Value>> {
     let redis_key = redis_key(&uuid);
     // try load cache from redis
     let mut redis = state.as_ref().redis_connection().await?;
@@ -38,8 +96,8 @@ async fn message_detail(
             Ok(v) => {
                 log::debug!("Hit redis cache");
                 return Ok(match v.is_null() {
-                    true => HttpResponse::NotFound().json(json!({})),
-                    false => HttpResponse::Ok().json(v),
+                    true => None,
+                    false => Some(v),
                 });
             }
             // parse error, wrong value, should be seeing this
@@ -57,61 +115,827 @@ async fn message_detail(
     // cache result to redis and return
     Ok(match message {
         None => {
-            redis.set_ex(redis_key, "null", MISS_CACHE_SECONDS).await?;
-            HttpResponse::NotFound().json(json!({}))
+            let _: () = redis.set_ex(redis_key, "null", MISS_CACHE_SECONDS).await?;
+            None
         }
         Some(msg) => {
             let msg = simplify_message(msg);
-            redis
-                .set_ex(&redis_key, msg.to_string(), HIT_CACHE_SECONDS)
+            let _: () = redis
+                .set_ex(&redis_key, msg.to_string(), cache_seconds(&msg))
                 .await?;
-            HttpResponse::Ok().json(msg)
+            Some(msg)
+        }
+    })
+}
+
+/// Record a view of the detail page, failures are logged but not shown to the viewer.
+async fn record_view(uuid: Uuid, request: &HttpRequest, state: web::Data<AppState>) {
+    let user_agent = crate::utils::get_user_agent(request).unwrap_or_default();
+    let view = NewMessageView {
+        message_id: uuid,
+        viewed_time: unix_timestamp(),
+        client: crate::utils::client_type(&user_agent).to_owned(),
+    };
+    let client = view.client.clone();
+    let db_state = state.clone();
+    let result = web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        super::actions::insert_view(&view, &con)?;
+        // only the first view is reported to webhooks
+        match super::actions::count_views(uuid, &con)? {
+            1 => Ok(super::actions::find_message_by_uuid(uuid, &con)?.map(|m| m.receiver_id)),
+            _ => Ok(None),
+        }
+    })
+    .await;
+    match result {
+        Ok(Some(receiver)) => {
+            let data = json!({ "client": client });
+            webhook::emit(&state, Event::Viewed, &receiver, uuid, data, None).await;
+        }
+        Ok(None) => {}
+        Err(e) => log::warn!(
+            "Failed to record view of message {}: {}",
+            uuid,
+            Error::from(e)
+        ),
+    }
+}
+
+#[derive(Deserialize)]
+struct DetailQuery {
+    /// the receiver and their ack token, from the detail url
+    by: Option<String>,
+    ack: Option<String>,
+}
+
+/// Message detail, as a html page for browsers (`Accept: text/html`) or json otherwise.
+///
+/// The page offers to acknowledge the message if opened with a valid ack token.
+async fn message_detail(
+    params: web::Path<(Uuid,)>,
+    query: web::Query<DetailQuery>,
+    state: web::Data<AppState>,
+    request: HttpRequest,
+) -> Result<HttpResponse> {
+    let uuid = params.0;
+    let message = load_message(uuid, state.clone()).await?;
+    let action = format!("{}/message/{}/ack", state.config.root_url, uuid);
+    let ack_form = match (&query.by, &query.ack) {
+        (Some(by), Some(ack))
+            if ack_token::verify(&state.config.secret_key, &uuid, by, ack).is_ok() =>
+        {
+            Some(AckForm {
+                action: &action,
+                by,
+                ack,
+            })
+        }
+        _ => None,
+    };
+    let html = crate::utils::accepts_html(&request);
+    let gone = message.as_ref().map(is_gone).unwrap_or(false);
+    // a page view is a human reading the message, api calls are not counted
+    if html && message.is_some() && !gone {
+        record_view(uuid, &request, state).await;
+    }
+    let mut response = match (&message, gone) {
+        (None, _) => HttpResponse::NotFound(),
+        (Some(_), true) => HttpResponse::Gone(),
+        (Some(_), false) => HttpResponse::Ok(),
+    };
+    response.header("Vary", "Accept");
+    Ok(match (message, gone, html) {
+        (Some(msg), false, true) => response
+            .content_type("text/html; charset=utf-8")
+            .body(pages::message_page(&msg, ack_form)),
+        (Some(_), true, true) => response
+            .content_type("text/html; charset=utf-8")
+            .body(pages::gone_page()),
+        (None, _, true) => response
+            .content_type("text/html; charset=utf-8")
+            .body(pages::not_found_page()),
+        (Some(msg), false, false) => response.json(msg),
+        (_, _, false) => response.json(json!({})),
+    })
+}
+
+/// Messages held in quiet hours or for digest mode and pushed together, as a html page or json.
+async fn digest_detail(
+    params: web::Path<(Uuid,)>,
+    state: web::Data<AppState>,
+    request: HttpRequest,
+) -> Result<HttpResponse> {
+    let uuid = params.0;
+    let db_state = state.clone();
+    let messages = web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        super::actions::find_digest_messages(uuid, &con)
+    })
+    .await?;
+    let html = crate::utils::accepts_html(&request);
+    let mut response = match messages.is_empty() {
+        true => HttpResponse::NotFound(),
+        false => HttpResponse::Ok(),
+    };
+    response.header("Vary", "Accept");
+    if !html {
+        let messages: Vec<Value> = messages
+            .iter()
+            .map(|m| {
+                json!({
+                    "token": m.id,
+                    "title": m.title,
+                    "created_time": m.created_time,
+                    "priority": m.priority,
+                })
+            })
+            .collect();
+        return Ok(response.json(json!({ "messages": messages })));
+    }
+    let body = match messages.iter().map(|m| m.created_time).max() {
+        None => pages::not_found_page(),
+        Some(created_time) => {
+            let urls: Vec<String> = messages
+                .iter()
+                .map(|m| {
+                    let account = state.config.account_by_app_id(&m.app_id);
+                    detail_url(&state.config, account, &m.id, &m.receiver_id)
+                })
+                .collect();
+            let items: Vec<DigestItem> = messages
+                .iter()
+                .zip(urls.iter())
+                .map(|(m, url)| DigestItem {
+                    title: &m.title,
+                    created_time: m.created_time,
+                    url,
+                })
+                .collect();
+            pages::digest_page(created_time, &items)
+        }
+    };
+    Ok(response.content_type("text/html; charset=utf-8").body(body))
+}
+
+#[derive(Deserialize)]
+struct SendkeyQuery {
+    sendkey: String,
+}
+
+/// Find the message from database, authorised by the receiver's send key.
+async fn find_authorised_message(
+    uuid: Uuid,
+    sendkey: &str,
+    state: &web::Data<AppState>,
+) -> Result<Message> {
+    let db_state = state.clone();
+    let message = web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        super::actions::find_message_by_uuid(uuid, &con)
+    })
+    .await?
+    .ok_or_else(|| Error::NotFound("Message not found".to_owned()))?;
+    crate::sendkey::verify(&state.config.secret_key, &message.receiver_id, sendkey)?;
+    rate_limit::check(state, Bucket::Sender, sendkey).await?;
+    Ok(message)
+}
+
+/// Revoke a message, e.g. one sent by mistake, authorised by the receiver's send key.
+///
+/// The content is erased and the message is gone from then on.
+async fn revoke_message(
+    params: web::Path<(Uuid,)>,
+    query: web::Query<SendkeyQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let uuid = params.0;
+    find_authorised_message(uuid, &query.sendkey, &state).await?;
+
+    let db_state = state.clone();
+    web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        super::actions::revoke_message(uuid, unix_timestamp(), &con)
+    })
+    .await?;
+    // purge cache immediately
+    let mut redis = state.as_ref().redis_connection().await?;
+    let _: () = redis.del(redis_key(&uuid)).await?;
+    log::info!("Message {} revoked", uuid);
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+#[derive(Deserialize)]
+struct AckParams {
+    by: String,
+    ack: String,
+}
+
+/// Acknowledge a message, authorised by the ack token of a receiver from the detail url.
+///
+/// Only the first acknowledgement is recorded. It cancels pending escalations
+/// and is posted to the `ack_webhook` of the message if any.
+async fn acknowledge_message(
+    params: web::Path<(Uuid,)>,
+    form: web::Form<AckParams>,
+    state: web::Data<AppState>,
+    request: HttpRequest,
+) -> Result<HttpResponse> {
+    let uuid = params.0;
+    let AckParams { by, ack } = form.into_inner();
+    ack_token::verify(&state.config.secret_key, &uuid, &by, &ack)?;
+
+    let db_state = state.clone();
+    let (acked, message) = web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        let acked = super::actions::acknowledge_message(uuid, &by, unix_timestamp(), &con)? > 0;
+        if acked {
+            super::actions::cancel_escalations(uuid, &con)?;
         }
+        let message = super::actions::find_message_by_uuid(uuid, &con)?;
+        Ok((acked, message))
+    })
+    .await?;
+    let message = message.ok_or_else(|| Error::NotFound("Message not found".to_owned()))?;
+    let (acked_time, acked_by) = match (message.acked_time, &message.acked_by) {
+        (Some(time), Some(by)) => (time, by.clone()),
+        _ => return Err(Error::BadRequest("Message is gone".to_owned())),
+    };
+
+    if acked {
+        log::info!("Message {} acknowledged", uuid);
+        let mut redis = state.as_ref().redis_connection().await?;
+        let _: () = redis.del(redis_key(&uuid)).await?;
+        let data = json!({ "acked_time": acked_time, "acked_by": acked_by });
+        let receiver = &message.receiver_id;
+        webhook::emit(
+            &state,
+            Event::Acknowledged,
+            receiver,
+            uuid,
+            data,
+            message.ack_webhook,
+        )
+        .await;
+    }
+
+    Ok(match crate::utils::accepts_html(&request) {
+        true => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(pages::acked_page(acked_time)),
+        false => HttpResponse::Ok().json(json!({
+            "acked_time": acked_time,
+            "acked_by": acked_by,
+        })),
+    })
+}
+
+/// Status of a message for its sender, authorised by the receiver's send key.
+///
+/// Includes views of the detail page, so that one can tell whether the message was seen.
+async fn message_status(
+    params: web::Path<(Uuid,)>,
+    query: web::Query<SendkeyQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let uuid = params.0;
+    let message = find_authorised_message(uuid, &query.sendkey, &state).await?;
+    let (stats, escalation) = web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        let stats = super::actions::find_view_stats(uuid, &con)?;
+        let escalation = super::actions::find_escalation_by_message(uuid, &con)?;
+        Ok((stats, escalation))
+    })
+    .await?;
+
+    let clients: serde_json::Map<String, Value> = stats
+        .iter()
+        .map(|s| (s.client.clone(), json!(s.count)))
+        .collect();
+    let status = json!({
+        "token": message.id,
+        "created_time": message.created_time,
+        "expires_at": message.expires_at,
+        "revoked_time": message.revoked_time,
+        "acked_time": message.acked_time,
+        "acked_by": message.acked_by,
+        "duplicates": message.duplicates,
+        "priority": message.priority,
+        "held_until": message.held_until,
+        "digest": message.digest_id,
+        "channel": message.channel,
+        "views": {
+            "count": stats.iter().map(|s| s.count).sum::<i64>(),
+            "first_viewed_time": stats.iter().map(|s| s.first_viewed_time).min(),
+            "last_viewed_time": stats.iter().map(|s| s.last_viewed_time).max(),
+            "clients": clients,
+        },
+        "escalation": escalation.map(|e| json!({
+            "status": e.status,
+            "step": e.step,
+            "max_steps": e.max_steps,
+            "next_time": e.next_time,
+        })),
+    });
+    Ok(HttpResponse::Ok().json(status))
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    receiver: String,
+    sendkey: String,
+    /// the `next` cursor of the previous page
+    before: Option<String>,
+    limit: Option<i64>,
+}
+
+fn encode_cursor(message: &Message) -> String {
+    format!("{}_{}", message.created_time, message.id)
+}
+
+fn decode_cursor(cursor: &str) -> Result<(i64, Uuid)> {
+    let bad_cursor = || Error::BadRequest("Invalid cursor".to_owned());
+    let mut parts = cursor.splitn(2, '_');
+    let time = parts
+        .next()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(bad_cursor)?;
+    let id = parts
+        .next()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(bad_cursor)?;
+    Ok((time, id))
+}
+
+/// List messages of a receiver, newest first, authorised by the receiver's send key.
+///
+/// Returns `{ messages, next }` where `next` is the cursor for the next page, or null on the last page.
+async fn list_messages(
+    query: web::Query<ListQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &query.receiver, &query.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &query.sendkey).await?;
+    let before = query.before.as_deref().map(decode_cursor).transpose()?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let receiver = query.receiver;
+    let messages = web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        super::actions::find_messages_by_receiver(&receiver, before, limit, &con)
+    })
+    .await?;
+
+    let next = match messages.len() as i64 == limit {
+        true => messages.last().map(encode_cursor),
+        false => None,
+    };
+    let messages: Vec<Value> = messages
+        .into_iter()
+        .map(|msg| {
+            let id = msg.id;
+            let mut simplified = simplify_message(msg);
+            simplified["token"] = json!(id);
+            simplified
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({ "messages": messages, "next": next })))
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    receiver: String,
+    sendkey: String,
+    q: String,
+    limit: Option<i64>,
+}
+
+fn highlight(s: &str) -> String {
+    use super::actions::{HIGHLIGHT_START, HIGHLIGHT_STOP};
+    crate::html::escape(s)
+        .replace(HIGHLIGHT_START, "<mark>")
+        .replace(HIGHLIGHT_STOP, "</mark>")
+}
+
+/// Full-text search over messages of a receiver, authorised by the receiver's send key.
+///
+/// Title and snippet in results are html escaped, with matched words in `<mark>`.
+async fn search_messages(
+    query: web::Query<SearchQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &query.receiver, &query.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &query.sendkey).await?;
+    if query.q.trim().is_empty() {
+        return Err(Error::BadRequest("Empty search query".to_owned()));
+    }
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    let results = web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        super::actions::search_messages(&query.receiver, &query.q, limit, &con)
     })
+    .await?;
+    let messages: Vec<Value> = results
+        .into_iter()
+        .map(|result| {
+            json!({
+                "token": result.id,
+                "title": highlight(&result.title),
+                "snippet": highlight(&result.snippet),
+                "created_time": result.created_time,
+            })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({ "messages": messages })))
+}
+
+/// The escalation policy requested along with the message, if any.
+fn new_escalation(message: &NewMessage, id: Uuid) -> Result<Option<NewEscalation>> {
+    let minutes = match message.escalate_after {
+        Some(minutes) if minutes > 0 => minutes,
+        Some(_) => return Err(Error::BadRequest("escalate_after must be positive".into())),
+        None => return Ok(None),
+    };
+    let receivers: Vec<String> = message
+        .escalate_to
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_owned)
+        .collect();
+    let max_steps = message.escalate_steps.unwrap_or(1 + receivers.len() as i32);
+    if !(1..=MAX_ESCALATION_STEPS).contains(&max_steps) {
+        return Err(Error::BadRequest(format!(
+            "escalate_steps must be between 1 and {}",
+            MAX_ESCALATION_STEPS
+        )));
+    }
+    let interval_seconds = minutes as i64 * 60;
+    Ok(Some(NewEscalation {
+        message_id: id,
+        receivers,
+        max_steps,
+        interval_seconds,
+        next_time: unix_timestamp() + interval_seconds,
+    }))
+}
+
+/// Take tokens from the buckets of the client ip, the sender if it sent its send key,
+/// and the receiver.
+async fn check_rate_limits(
+    state: &web::Data<AppState>,
+    request: &HttpRequest,
+    message: &NewMessage,
+) -> Result<()> {
+    rate_limit::check_ip(state, request).await?;
+    // only valid send keys get a bucket
+    if let (Some(sendkey), Some(_)) = (&message.sendkey, sender(state, message)?) {
+        rate_limit::check(state, Bucket::Sender, sendkey).await?;
+    }
+    rate_limit::check(state, Bucket::Receiver, &message.receiver).await
+}
+
+/// Send a new message without an idempotency key, returning the response.
+pub async fn send(
+    message: NewMessage,
+    state: web::Data<AppState>,
+    request: &HttpRequest,
+) -> Result<Value> {
+    check_rate_limits(&state, request, &message).await?;
+    let sent = push_message(message, state.clone(), request).await?;
+    save_message(sent, &state).await
 }
 
+/// POST /message
 async fn post_message(
     message: web::Form<NewMessage>,
     state: web::Data<AppState>,
     request: HttpRequest,
 ) -> Result<HttpResponse> {
     // extract from web::Form boxing
-    let mut message: NewMessage = message.into_inner();
-    // modify the message
-    let id = Uuid::new_v4();
-    message.id = Some(id.clone());
-    message.detail_url = Some(format!(
-        "{}/{}",
-        state.as_ref().config.wechat.detail_url,
-        message.id.as_ref().unwrap()
-    ));
-    message.template_id = Some(
-        message
-            .template_id
-            .unwrap_or_else(|| state.as_ref().config.wechat.default_template_id.clone()),
-    );
+    submit(message.into_inner(), state, &request).await
+}
+
+/// Send a new message, or replay the response of a previous request with the same idempotency key.
+///
+/// The key is taken from the `Idempotency-Key` header, or the `idempotency_key` field.
+pub async fn submit(
+    mut message: NewMessage,
+    state: web::Data<AppState>,
+    request: &HttpRequest,
+) -> Result<HttpResponse> {
+    check_rate_limits(&state, request, &message).await?;
+    let key = match request.headers().get(idempotency::HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .map_err(|_| Error::BadRequest("Bad idempotency key".into()))?
+                .to_owned(),
+        ),
+        None => message.idempotency_key.take(),
+    };
+    let key = match key {
+        Some(key) => key,
+        None => {
+            let sent = push_message(message, state.clone(), request).await?;
+            let response = save_message(sent, &state).await?;
+            return Ok(HttpResponse::Ok().json(response));
+        }
+    };
+    if key.is_empty() || key.len() > idempotency::MAX_KEY_LEN {
+        return Err(Error::BadRequest("Bad idempotency key".into()));
+    }
+
+    let receiver = message.receiver.clone();
+    let fingerprint = idempotency::fingerprint(&key, &message);
+    let mut redis = state.as_ref().redis_connection().await?;
+    match idempotency::claim(&mut redis, &receiver, &key, &fingerprint).await? {
+        Claim::New => {}
+        Claim::Done(response) => {
+            log::info!("Replaying response of idempotency key {}", key);
+            return Ok(HttpResponse::Ok()
+                .header("Idempotent-Replayed", "true")
+                .json(response));
+        }
+        Claim::InProgress => {
+            return Err(Error::Conflict(
+                "A request with the same idempotency key is in progress".into(),
+            ))
+        }
+        Claim::Mismatch => {
+            return Err(Error::BadRequest(
+                "The idempotency key was used for another message".into(),
+            ))
+        }
+    }
+    let sent = match push_message(message, state.clone(), request).await {
+        Ok(sent) => sent,
+        // let the client retry
+        Err(e) => {
+            idempotency::release(&mut redis, &receiver, &key).await?;
+            return Err(e);
+        }
+    };
+    let (token, pushed) = (sent.token(), sent.pushed());
+    let result = save_message(sent, &state).await;
+    match &result {
+        Ok(response) => {
+            idempotency::complete(&mut redis, &receiver, &key, &fingerprint, response).await?
+        }
+        // pushed but not saved, a retry must not push it again
+        Err(_) if pushed => {
+            let response = json!({ "token": token });
+            idempotency::complete(&mut redis, &receiver, &key, &fingerprint, &response).await?
+        }
+        Err(_) => idempotency::release(&mut redis, &receiver, &key).await?,
+    }
+    Ok(HttpResponse::Ok().json(result?))
+}
+
+/// Name of the account to push the message by, the one the receiver subscribed to unless specified.
+async fn receiver_account(state: &web::Data<AppState>, message: &NewMessage) -> Result<String> {
+    if let Some(account) = &message.account {
+        return Ok(account.clone());
+    }
+    let db_state = state.clone();
+    let receiver = message.receiver.clone();
+    let found = web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        super::actions::find_receiver_account(&receiver, &con)
+    })
+    .await?;
+    Ok(found.unwrap_or_else(|| DEFAULT_ACCOUNT.to_owned()))
+}
+
+/// When to push a message of the priority to the receiver, None for right away.
+async fn hold_until(
+    state: &web::Data<AppState>,
+    receiver: &str,
+    priority: Priority,
+) -> Result<Option<i64>> {
+    if priority == Priority::Critical {
+        return Ok(None);
+    }
+    let db_state = state.clone();
+    let receiver = receiver.to_owned();
+    let found = web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        crate::routes::preferences::actions::find_preferences(&receiver, &con)
+    })
+    .await?;
+    Ok(found.and_then(|p| preferences::hold_until(&p, priority, unix_timestamp())))
+}
+
+/// Push the message by the delivery channels of the receiver.
+async fn push(
+    state: &web::Data<AppState>,
+    message: &NewMessage,
+    id: Uuid,
+    dedup_until: Option<i64>,
+) -> Result<Delivery> {
     log::trace!("Sending message {:?}", message);
-    // post message with wechat module api
-    let response = apis::send_template_message(&state.as_ref().token_manager, &message).await;
-    use crate::wechat::errors::WechatError;
-    if let Err(WechatError::Wechat { errcode, .. }) = &response {
+    let response = delivery::push(state, message).await;
+    if let Err(e) = &response {
+        if let (Some(key), Some(_)) = (&message.dedup_key, dedup_until) {
+            let mut redis = state.as_ref().redis_connection().await?;
+            dedup::leave(&mut redis, &message.receiver, key, id).await?;
+        }
+        let data = json!({ "reason": e.to_string() });
+        webhook::emit(state, Event::Failed, &message.receiver, id, data, None).await;
+    }
+    use crate::wechat::{errors::WechatError, wecom};
+    if let Err(DeliveryError::Wechat(WechatError::Wechat { errcode, .. })) = &response {
         if *errcode == 40003 {
             log::warn!("OpenID illegal");
             return Err(Error::BadRequest("OpenID illegal".into()));
         }
+        if *errcode == wecom::INVALID_USER {
+            log::warn!("WeCom user id illegal");
+            return Err(Error::BadRequest("UserID illegal".into()));
+        }
+    }
+    let delivery = response?;
+    match &delivery {
+        Delivery::Sent { channel, .. } => {
+            log::info!("Message {} was sent by {}", id, channel.as_str());
+        }
+        Delivery::Queued => log::warn!("Quota reached, message {} queued", id),
+    }
+    Ok(delivery)
+}
+
+/// A message after it went out.
+enum Sent {
+    /// counted as a duplicate of a previous message, with the response
+    Duplicate(Uuid, Value),
+    /// pushed, queued or held, to be saved
+    Pushed {
+        message: Box<Message>,
+        escalation: Option<NewEscalation>,
+    },
+}
+
+impl Sent {
+    fn token(&self) -> Uuid {
+        match self {
+            Sent::Duplicate(id, _) => *id,
+            Sent::Pushed { message, .. } => message.id,
+        }
+    }
+
+    /// Whether the message reached the receiver, rather than being queued or held.
+    fn pushed(&self) -> bool {
+        match self {
+            Sent::Duplicate(..) => true,
+            Sent::Pushed { message, .. } => !message.queued && message.held_until.is_none(),
+        }
+    }
+}
+
+/// Check and push the message, or hold it. Nothing went out if this fails.
+/// The sender of the message, i.e. the receiver if it's sent with their send key.
+fn sender(state: &AppState, message: &NewMessage) -> Result<Option<String>> {
+    match &message.sendkey {
+        Some(sendkey) => {
+            crate::sendkey::verify(&state.config.secret_key, &message.receiver, sendkey)?;
+            Ok(Some(message.receiver.clone()))
+        }
+        None => Ok(None),
     }
-    response?;
-    log::info!("A template message was sent successfully");
+}
+
+async fn push_message(
+    mut message: NewMessage,
+    state: web::Data<AppState>,
+    request: &HttpRequest,
+) -> Result<Sent> {
+    let sender_id = sender(&state, &message)?;
+    // modify the message
+    let id = Uuid::new_v4();
+    let account = receiver_account(&state, &message).await?;
+    let config = &state.as_ref().config;
+    let app_id = config
+        .app_id(&account)
+        .ok_or_else(|| Error::BadRequest(format!("Unknown account {}", account)))?;
+    message.id = Some(id.clone());
+    message.detail_url = Some(detail_url(config, &account, &id, &message.receiver));
+    // WeCom apps have no templates
+    let default_template_id = config
+        .account(&account)
+        .map(|wechat| wechat.default_template_id.clone())
+        .unwrap_or_default();
+    message.template_id = Some(message.template_id.unwrap_or(default_template_id));
+    message.account = Some(account);
+    if let Some(expires_at) = message.expires_at {
+        if expires_at <= unix_timestamp() {
+            return Err(Error::BadRequest("expires_at is in the past".into()));
+        }
+    }
+    if let Some(url) = &message.ack_webhook {
+        webhook::check_url("ack_webhook", url).await?;
+    }
+    let mut escalation = new_escalation(&message, id)?;
+    // set if the message opens a dedup window
+    let mut dedup_until = None;
+    if let Some(key) = &message.dedup_key {
+        let minutes = message
+            .dedup_window
+            .unwrap_or(dedup::DEFAULT_WINDOW_MINUTES);
+        if key.is_empty() || key.len() > dedup::MAX_KEY_LEN {
+            return Err(Error::BadRequest("Bad dedup_key".into()));
+        }
+        if !(1..=dedup::MAX_WINDOW_MINUTES).contains(&minutes) {
+            return Err(Error::BadRequest(format!(
+                "dedup_window must be between 1 and {} minutes",
+                dedup::MAX_WINDOW_MINUTES
+            )));
+        }
+        let window = minutes as usize * 60;
+        let mut redis = state.as_ref().redis_connection().await?;
+        match dedup::join(&mut redis, &message.receiver, key, id, window).await? {
+            None => dedup_until = Some(unix_timestamp() + window as i64),
+            Some(first) => {
+                let db_state = state.clone();
+                let counted = web::block(move || {
+                    let con = db_state.as_ref().db_pool.get()?;
+                    super::actions::count_duplicate(first, &con)
+                })
+                .await?;
+                if let Some(duplicates) = counted {
+                    let _: () = redis.del(redis_key(&first)).await?;
+                    log::info!("Message deduplicated into {}", first);
+                    let response = json!({ "token": first, "duplicates": duplicates });
+                    return Ok(Sent::Duplicate(first, response));
+                }
+                // the first one is not saved yet or failed to send, push this one anyway
+            }
+        }
+    }
+    let format = message.format.unwrap_or_default();
+    message.summary = message
+        .body
+        .as_ref()
+        .map(|body| summarize(body, format, SUMMARY_MAX_CHARS));
+    let priority = message.priority.unwrap_or_default();
+    let held_until = hold_until(&state, &message.receiver, priority).await?;
+    let delivery = match held_until {
+        // pushed in a digest by `jobs::digest`
+        Some(until) => {
+            log::info!("Message {} held till {}", id, until);
+            if let Some(escalation) = escalation.as_mut() {
+                escalation.next_time = until + escalation.interval_seconds;
+            }
+            None
+        }
+        None => Some(push(&state, &message, id, dedup_until).await?),
+    };
+    let queued = matches!(delivery, Some(Delivery::Queued));
+    let (wechat_msg_id, channel) = match &delivery {
+        Some(Delivery::Sent { channel, response }) => (
+            response["msgid"].as_i64(),
+            Some(channel.as_str().to_owned()),
+        ),
+        _ => (None, None),
+    };
     // success, not write to database
     // build Message type
     let msg = Message {
         id: message.id.unwrap(),
-        app_id: state.as_ref().config.wechat.app_id.clone(),
+        app_id,
         template_id: message.template_id.unwrap(),
         receiver_id: message.receiver,
         title: message.title,
         body: message.body.unwrap_or_default(),
         url: message.url,
+        format: format.as_str().to_owned(),
+        expires_at: message.expires_at,
+        revoked_time: None,
+        acked_time: None,
+        acked_by: None,
+        ack_webhook: message.ack_webhook,
+        wechat_msg_id,
+        dedup_key: dedup_until.and(message.dedup_key),
+        dedup_until,
+        duplicates: 0,
+        dedup_closed: false,
+        queued,
+        priority: priority.as_str().to_owned(),
+        held_until,
+        digest_id: None,
+        channel,
+        queue_lease: None,
+        sender_id,
         created_time: SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
@@ -121,42 +945,113 @@ async fn post_message(
             .remote()
             .unwrap_or("Failed to parse IP address")
             .into(),
-        UA: crate::utils::get_user_agent(&request)
+        UA: crate::utils::get_user_agent(request)
             .map_err(|e| {
                 log::warn!("Bad UA: {}", e);
                 e
             })
             .unwrap_or_default(),
     };
+    Ok(Sent::Pushed {
+        message: Box::new(msg),
+        escalation,
+    })
+}
+
+/// Save the message that went out, returns the response.
+async fn save_message(sent: Sent, state: &web::Data<AppState>) -> Result<Value> {
+    let (msg, escalation) = match sent {
+        Sent::Duplicate(_, response) => return Ok(response),
+        Sent::Pushed {
+            message,
+            escalation,
+        } => (*message, escalation),
+    };
+    let (id, queued, held_until) = (msg.id, msg.queued, msg.held_until);
     // save to redis cache
     let mut redis = state.as_ref().redis_connection().await?;
-    redis
+    let simplified = simplify_message(msg.clone());
+    let _: () = redis
         .set_ex(
             redis_key(&msg.id),
-            simplify_message(msg.clone()).to_string(),
-            HIT_CACHE_SECONDS,
+            simplified.to_string(),
+            cache_seconds(&simplified),
         )
         .await?;
     // insert into SQL database
     // log::debug!("Inserting {:?} into database", msg);
+    let receiver = msg.receiver_id.clone();
+    let db_state = state.clone();
     web::block(move || {
-        let con = state.as_ref().db_pool.get()?;
-        super::actions::insert_message(&msg, &con)
+        let con = db_state.as_ref().db_pool.get()?;
+        super::actions::insert_message(&msg, &con)?;
+        if let Some(escalation) = escalation {
+            super::actions::insert_escalation(&escalation, &con)?;
+        }
+        Ok(())
     })
     .await?;
+    if queued {
+        return Ok(json!({ "token": id, "queued": true }));
+    }
+    if let Some(until) = held_until {
+        return Ok(json!({ "token": id, "held_until": until }));
+    }
+    webhook::emit(state, Event::Sent, &receiver, id, json!({}), None).await;
 
-    Ok(HttpResponse::Ok().json(json!({ "token": id })))
+    Ok(json!({ "token": id }))
 }
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::resource("/message/{token}")
             .name("message_detail")
-            .route(web::get().to(message_detail)),
+            .route(web::get().to(message_detail))
+            .route(web::delete().to(revoke_message)),
+    )
+    .service(
+        web::resource("/message/{token}/ack")
+            .name("acknowledge message")
+            .route(web::post().to(acknowledge_message)),
+    )
+    .service(
+        web::resource("/message/{token}/status")
+            .name("message status")
+            .route(web::get().to(message_status)),
+    )
+    .service(
+        web::resource("/digest/{id}")
+            .name("digest")
+            .route(web::get().to(digest_detail)),
     )
     .service(
         web::resource("/message")
             .name("post new message")
             .route(web::post().to(post_message)),
+    )
+    .service(
+        web::resource("/messages")
+            .name("list messages of receiver")
+            .route(web::get().to(list_messages)),
+    )
+    .service(
+        web::resource("/messages/search")
+            .name("search messages of receiver")
+            .route(web::get().to(search_messages)),
     );
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_digest_url() {
+        let config = Config::new().unwrap();
+        let id = Uuid::new_v4();
+        let url = url::Url::parse(&digest_url(&config, &id)).unwrap();
+        assert!(url.scheme() == "http" || url.scheme() == "https");
+        assert!(url.has_host());
+        assert!(url.path().ends_with(&format!("/digest/{}", id)));
+    }
+}
@@ -1,6 +1,12 @@
+pub mod admin;
+pub mod alertmanager;
 pub mod callback;
+pub mod hook;
+pub mod login;
 pub mod message;
+pub mod preferences;
 pub mod scene;
+pub mod webhook;
 
 use crate::errors::{Error, Result};
 use actix_web::HttpResponse;
@@ -0,0 +1,30 @@
+use crate::errors::Result;
+use crate::models;
+
+use diesel::prelude::*;
+
+pub fn find_preferences(
+    receiver: &str,
+    con: &PgConnection,
+) -> Result<Option<models::ReceiverPreferences>> {
+    use crate::schema::receiver_preferences::dsl::*;
+    let mut found = receiver_preferences
+        .filter(receiver_id.eq(receiver))
+        .limit(1)
+        .load::<models::ReceiverPreferences>(con)?;
+    Ok(found.pop())
+}
+
+pub fn save_preferences(
+    preferences: &models::ReceiverPreferences,
+    con: &PgConnection,
+) -> Result<()> {
+    use crate::schema::receiver_preferences::dsl::*;
+    diesel::insert_into(receiver_preferences)
+        .values(preferences)
+        .on_conflict(receiver_id)
+        .do_update()
+        .set(preferences)
+        .execute(con)?;
+    Ok(())
+}
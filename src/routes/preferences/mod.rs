@@ -0,0 +1,5 @@
+mod routes;
+
+pub use routes::configure;
+
+pub mod actions;
@@ -0,0 +1,347 @@
+use crate::delivery::{self, ChannelKind};
+use crate::email;
+use crate::errors::{Error, Result};
+use crate::models::{DeliveryChannel, ReceiverPreferences};
+use crate::preferences::{
+    format_time, parse_time, Priority, MAX_DIGEST_INTERVAL, MIN_DIGEST_INTERVAL,
+};
+use crate::rate_limit::{self, Bucket};
+use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+use crate::webhook;
+use actix_web::{web, HttpResponse};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+const DEFAULT_TIMEZONE: &str = "Asia/Shanghai";
+
+fn default_preferences(receiver: String) -> ReceiverPreferences {
+    ReceiverPreferences {
+        receiver_id: receiver,
+        timezone: DEFAULT_TIMEZONE.to_owned(),
+        quiet_start: None,
+        quiet_end: None,
+        min_priority: Priority::High.as_str().to_owned(),
+        updated_time: 0,
+        digest_interval: None,
+    }
+}
+
+fn simplify_preferences(preferences: &ReceiverPreferences) -> Value {
+    json!({
+        "timezone": preferences.timezone,
+        "quiet_start": preferences.quiet_start.map(format_time),
+        "quiet_end": preferences.quiet_end.map(format_time),
+        "min_priority": preferences.min_priority,
+        "digest_interval": preferences.digest_interval,
+    })
+}
+
+#[derive(Deserialize)]
+struct ReceiverQuery {
+    receiver: String,
+    sendkey: String,
+}
+
+/// Delivery preferences of the receiver, authorised by the receiver's send key.
+async fn get_preferences(
+    query: web::Query<ReceiverQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &query.receiver, &query.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &query.sendkey).await?;
+    let receiver = query.receiver;
+    let preferences = web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        let found = super::actions::find_preferences(&receiver, &con)?;
+        Ok(found.unwrap_or_else(|| default_preferences(receiver)))
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(simplify_preferences(&preferences)))
+}
+
+#[derive(Deserialize)]
+struct PreferencesForm {
+    receiver: String,
+    sendkey: String,
+    timezone: Option<String>,
+    /// `HH:MM`, quiet hours are off if either is absent
+    quiet_start: Option<String>,
+    quiet_end: Option<String>,
+    min_priority: Option<String>,
+    /// minutes, messages are pushed in a digest at this interval if present
+    digest_interval: Option<String>,
+}
+
+/// Replace the delivery preferences of the receiver, authorised by the receiver's send key.
+async fn save_preferences(
+    form: web::Form<PreferencesForm>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let form = form.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &form.receiver, &form.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &form.sendkey).await?;
+
+    let timezone = form.timezone.unwrap_or_else(|| DEFAULT_TIMEZONE.to_owned());
+    if timezone.parse::<Tz>().is_err() {
+        return Err(Error::BadRequest(format!("Unknown timezone {}", timezone)));
+    }
+    let parse = |time: Option<String>| match time.filter(|s| !s.is_empty()) {
+        Some(s) => parse_time(&s)
+            .map(Some)
+            .ok_or_else(|| Error::BadRequest(format!("Bad time {}, expecting HH:MM", s))),
+        None => Ok(None),
+    };
+    let quiet_start = parse(form.quiet_start)?;
+    let quiet_end = parse(form.quiet_end)?;
+    let min_priority = match form.min_priority {
+        Some(s) => Priority::parse(&s)
+            .ok_or_else(|| Error::BadRequest(format!("Unknown priority {}", s)))?,
+        None => Priority::High,
+    };
+    let digest_interval = match form.digest_interval.filter(|s| !s.is_empty()) {
+        Some(s) => match s.parse::<i32>() {
+            Ok(minutes) if (MIN_DIGEST_INTERVAL..=MAX_DIGEST_INTERVAL).contains(&minutes) => {
+                Some(minutes)
+            }
+            _ => {
+                return Err(Error::BadRequest(format!(
+                    "digest_interval must be between {} and {} minutes",
+                    MIN_DIGEST_INTERVAL, MAX_DIGEST_INTERVAL
+                )))
+            }
+        },
+        None => None,
+    };
+    let preferences = ReceiverPreferences {
+        receiver_id: form.receiver,
+        timezone,
+        quiet_start,
+        quiet_end,
+        min_priority: min_priority.as_str().to_owned(),
+        updated_time: unix_timestamp(),
+        digest_interval,
+    };
+
+    let response = simplify_preferences(&preferences);
+    web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        super::actions::save_preferences(&preferences, &con)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// Delivery channels of the receiver in order, authorised by the receiver's send key.
+async fn get_channels(
+    query: web::Query<ReceiverQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &query.receiver, &query.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &query.sendkey).await?;
+    let receiver = query.receiver;
+    let channels = web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        delivery::actions::find_channels(&receiver, &con)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({ "channels": channels })))
+}
+
+#[derive(Deserialize)]
+struct ChannelsForm {
+    receiver: String,
+    sendkey: String,
+    /// tried in order, the default channel is used if empty
+    channels: Vec<DeliveryChannel>,
+}
+
+/// Check a channel of the receiver.
+async fn check_channel(channel: &DeliveryChannel, state: &AppState) -> Result<()> {
+    let kind = ChannelKind::parse(&channel.kind)
+        .ok_or_else(|| Error::BadRequest(format!("Unknown channel {}", channel.kind)))?;
+    let account = channel.account.as_deref();
+    match kind {
+        ChannelKind::Template | ChannelKind::Custom => {
+            if account.map_or(false, |a| state.config.account(a).is_none()) {
+                return Err(Error::BadRequest(format!(
+                    "Unknown account {}",
+                    account.unwrap()
+                )));
+            }
+        }
+        ChannelKind::Wecom => {
+            if account.and_then(|a| state.config.wecom_app(a)).is_none() {
+                return Err(Error::BadRequest(
+                    "wecom needs the name of a WeCom app".into(),
+                ));
+            }
+        }
+        // checked again on each delivery, as the host may resolve elsewhere later
+        ChannelKind::Webhook => match channel.target.as_deref() {
+            Some(url) => webhook::check_url("webhook", url).await?,
+            None => return Err(Error::BadRequest("webhook needs a http(s) url".into())),
+        },
+        // to the verified address, skipped till there is one
+        ChannelKind::Email => {
+            if !state.config.smtp.enabled() {
+                return Err(Error::BadRequest("Email is not enabled".into()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replace the delivery channels of the receiver, authorised by the receiver's send key.
+async fn save_channels(
+    form: web::Json<ChannelsForm>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let form = form.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &form.receiver, &form.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &form.sendkey).await?;
+    if form.channels.len() > delivery::MAX_CHANNELS {
+        return Err(Error::BadRequest(format!(
+            "At most {} channels can be set",
+            delivery::MAX_CHANNELS
+        )));
+    }
+    let receiver = form.receiver;
+    let mut channels = Vec::with_capacity(form.channels.len());
+    for (position, channel) in form.channels.into_iter().enumerate() {
+        check_channel(&channel, &state).await?;
+        channels.push(DeliveryChannel {
+            receiver_id: receiver.clone(),
+            position: position as i32,
+            ..channel
+        });
+    }
+
+    let response = json!({ "channels": channels });
+    web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        delivery::actions::save_channels(&receiver, &channels, &con)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// The verified email address of the receiver, authorised by the receiver's send key.
+async fn get_email(
+    query: web::Query<ReceiverQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &query.receiver, &query.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &query.sendkey).await?;
+    let receiver = query.receiver;
+    let email = web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        delivery::actions::find_email(&receiver, &con)
+    })
+    .await?
+    .ok_or_else(|| Error::NotFound("No verified email address".to_owned()))?;
+    Ok(HttpResponse::Ok().json(json!({
+        "address": email.address,
+        "always": email.always,
+        "verified_time": email.verified_time,
+    })))
+}
+
+#[derive(Deserialize)]
+struct EmailForm {
+    receiver: String,
+    sendkey: String,
+    address: String,
+    /// also email messages taken by other channels, not only as a fallback
+    always: Option<bool>,
+}
+
+/// Email a verification code to the address, authorised by the receiver's send key.
+async fn register_email(
+    form: web::Form<EmailForm>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let form = form.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &form.receiver, &form.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &form.sendkey).await?;
+    let always = form.always.unwrap_or(false);
+    let address =
+        email::request_verification(&state, &form.receiver, &form.address, always).await?;
+    Ok(HttpResponse::Accepted().json(json!({
+        "address": address.to_string(),
+        "expires_in": state.config.smtp.code_minutes * 60,
+    })))
+}
+
+#[derive(Deserialize)]
+struct VerifyEmailForm {
+    receiver: String,
+    sendkey: String,
+    code: String,
+}
+
+/// Verify the registered address by the emailed code, authorised by the receiver's send key.
+async fn verify_email(
+    form: web::Form<VerifyEmailForm>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let form = form.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &form.receiver, &form.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &form.sendkey).await?;
+    let email = email::verify(&state, &form.receiver, &form.code).await?;
+    Ok(HttpResponse::Ok().json(json!({
+        "address": email.address,
+        "always": email.always,
+    })))
+}
+
+/// Forget the email address of the receiver, authorised by the receiver's send key.
+async fn delete_email(
+    query: web::Query<ReceiverQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &query.receiver, &query.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &query.sendkey).await?;
+    let receiver = query.receiver;
+    let deleted = web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        delivery::actions::delete_email(&receiver, &con)
+    })
+    .await?;
+    match deleted {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Err(Error::NotFound("No verified email address".to_owned())),
+    }
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/preferences")
+            .name("delivery preferences of receiver")
+            .route(web::get().to(get_preferences))
+            .route(web::post().to(save_preferences)),
+    )
+    .service(
+        web::resource("/preferences/channels")
+            .name("delivery channels of receiver")
+            .route(web::get().to(get_channels))
+            .route(web::put().to(save_channels)),
+    )
+    .service(
+        web::resource("/preferences/email")
+            .name("email address of receiver")
+            .route(web::get().to(get_email))
+            .route(web::post().to(register_email))
+            .route(web::delete().to(delete_email)),
+    )
+    .service(
+        web::resource("/preferences/email/verify")
+            .name("verify email address of receiver")
+            .route(web::post().to(verify_email)),
+    );
+}
@@ -1,11 +1,29 @@
+use actix_web::web::Bytes;
 use actix_web::{web, HttpResponse};
+use futures::stream;
 use redis::AsyncCommands;
-use serde_json::json;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
 
-use crate::errors::Result;
+use super::login;
+use crate::config::DEFAULT_ACCOUNT;
+use crate::errors::{Error, Result};
+use crate::qr_image::QrMatrix;
+use crate::quota::{self, Api};
 use crate::shared_state::AppState;
+use crate::utils::{random_hex, secure_eq};
 use crate::wechat;
 
+const SCENE_EXPIRE_SECONDS: u64 = 5 * 60;
+const MAX_WAIT_SECONDS: u64 = 60;
+const HEARTBEAT_SECONDS: u64 = 15;
+const DEFAULT_QR_SIZE: u32 = 256;
+const MAX_QR_SIZE: u32 = 1024;
+const DEFAULT_QR_MARGIN: u32 = 4;
+const MAX_QR_MARGIN: u32 = 16;
+
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::resource("/scene")
@@ -16,19 +34,68 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
         web::resource("/scene/{scene_id}")
             .name("Query scene with id")
             .route(web::get().to(query_scene)),
+    )
+    .service(
+        web::resource("/scene/{scene_id}/events")
+            .name("Subscribe scene scan events")
+            .route(web::get().to(scene_events)),
+    )
+    .service(
+        web::resource("/scene/{scene_id}/qr.{format}")
+            .name("Render scene QR code")
+            .route(web::get().to(scene_qr_image)),
     );
 }
 
+fn scene_url_key(scene_id: u64) -> String {
+    format!("scene_url_{}", scene_id)
+}
+
+fn scene_secret_key(scene_id: u64) -> String {
+    format!("scene_secret_{}", scene_id)
+}
+
+#[derive(Deserialize)]
+struct CreateSceneQuery {
+    /// name of the official account to subscribe to, the default one if absent
+    account: Option<String>,
+}
+
 /// Creates a scan scene.
 ///
 /// basically it calls the [wechat API]
 /// [wechat API]: https://developers.weixin.qq.com/doc/offiaccount/Account_Management/Generating_a_Parametric_QR_Code.html
-async fn create_scene(state: web::Data<AppState>) -> Result<HttpResponse> {
+async fn create_scene(
+    query: web::Query<CreateSceneQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let account = query.account.as_deref().unwrap_or(DEFAULT_ACCOUNT);
+    if state.config.account(account).is_none() {
+        return Err(Error::BadRequest(format!("Unknown account {}", account)));
+    }
+    let token_manager = state.token_manager(account)?;
     // make a new QR Code scan scene
-    let scene = wechat::qrcode::create_new_temp(&state.token_manager, 5 * 60).await?;
+    let scene = wechat::qrcode::create_new_temp(token_manager, SCENE_EXPIRE_SECONDS as i32).await;
+    quota::record(&state, account, Api::QrcodeCreate).await;
+    let scene = scene?;
     log::info!("New scene generated");
     let scene_id = scene["scene_id"].as_u64().unwrap();
     let ticket = scene["ticket"].as_str().unwrap();
+    // save the content of the QR code so the image can be rendered locally
+    let url = scene["url"].as_str().unwrap();
+    let mut con = state.redis_connection().await?;
+    let _: () = con
+        .set_ex(scene_url_key(scene_id), url, SCENE_EXPIRE_SECONDS as usize)
+        .await?;
+    // the secret is required to query the scan result
+    let secret = random_hex(32);
+    let _: () = con
+        .set_ex(
+            scene_secret_key(scene_id),
+            &secret,
+            SCENE_EXPIRE_SECONDS as usize,
+        )
+        .await?;
     let qr_url = format!(
         "https://mp.weixin.qq.com/cgi-bin/showqrcode?ticket={}",
         ticket
@@ -38,20 +105,203 @@ async fn create_scene(state: web::Data<AppState>) -> Result<HttpResponse> {
         "scene_id": scene_id,
         "ticket": ticket,
         "qr_url": qr_url,
+        "secret": secret,
     });
 
     Ok(HttpResponse::Ok().json(response))
 }
 
-async fn query_scene(query: web::Path<(u32,)>, state: web::Data<AppState>) -> Result<HttpResponse> {
-    let scene_id = query.0;
-    // query from redis
-    let mut con = state.as_ref().redis_connection().await?;
+#[derive(Deserialize)]
+struct QrQuery {
+    /// image width in pixels
+    size: Option<u32>,
+    /// quiet zone around the code, in modules
+    margin: Option<u32>,
+}
+
+/// Renders the QR code of the scene as `qr.png` or `qr.svg`.
+///
+/// The image is generated from the scene url returned by wechat, so clients
+/// need not fetch it from wechat's image host.
+async fn scene_qr_image(
+    params: web::Path<(u32, String)>,
+    query: web::Query<QrQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let (scene_id, format) = params.into_inner();
+    let size = query.size.unwrap_or(DEFAULT_QR_SIZE).min(MAX_QR_SIZE);
+    let margin = query.margin.unwrap_or(DEFAULT_QR_MARGIN).min(MAX_QR_MARGIN);
+
+    let mut con = state.redis_connection().await?;
+    let url: Option<String> = con.get(scene_url_key(scene_id as u64)).await?;
+    let url = url.ok_or_else(|| Error::NotFound("Scene not found or expired".to_owned()))?;
+    let qr = QrMatrix::new(&url, margin)?;
+
+    let mut response = HttpResponse::Ok();
+    response.header(
+        "Cache-Control",
+        format!("private, max-age={}", SCENE_EXPIRE_SECONDS),
+    );
+    match format.as_str() {
+        "png" => Ok(response.content_type("image/png").body(qr.to_png(size)?)),
+        "svg" => Ok(response.content_type("image/svg+xml").body(qr.to_svg(size))),
+        _ => Err(Error::NotFound(format!(
+            "Unsupported image format {}",
+            format
+        ))),
+    }
+}
+
+#[derive(Deserialize)]
+struct SceneQuery {
+    /// the secret returned when the scene was created
+    secret: String,
+    /// long-poll for at most this many seconds if the scene is not scanned yet
+    wait: Option<u64>,
+}
+
+async fn verify_scene_secret(state: &AppState, scene_id: u32, secret: &str) -> Result<()> {
+    let mut con = state.redis_connection().await?;
+    let stored: Option<String> = con.get(scene_secret_key(scene_id as u64)).await?;
+    match stored {
+        Some(stored) if secure_eq(&stored, secret) => Ok(()),
+        _ => Err(Error::Unauthorized("Invalid scene secret".to_owned())),
+    }
+}
+
+/// Take the open id of a scanned scene, atomically, so that only one login token is issued.
+async fn claim_scene_open_id(state: &AppState, scene_id: u32) -> Result<Option<String>> {
+    let mut con = state.redis_connection().await?;
     let key: String = format!("scene_{}", scene_id);
-    let response: Option<String> = con.get(&key).await?;
+    let (open_id, _): (Option<String>, i32) = redis::pipe()
+        .atomic()
+        .get(&key)
+        .del(&key)
+        .query_async(&mut con)
+        .await?;
+    Ok(open_id)
+}
+
+async fn claim_login_token(state: &AppState, scene_id: u32) -> Result<Option<Value>> {
+    match claim_scene_open_id(state, scene_id).await? {
+        Some(open_id) => Ok(Some(login::issue_login_token(state, open_id).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Wait until the scene is scanned, or until timeout.
+async fn wait_login_token(
+    state: &AppState,
+    scene_id: u32,
+    timeout: Duration,
+) -> Result<Option<Value>> {
+    // subscribe before querying so a scan in between is not missed
+    let receiver = state.scene_notifier.wait(&scene_id.to_string());
+    if let Some(token) = claim_login_token(state, scene_id).await? {
+        return Ok(Some(token));
+    }
+    match tokio::time::timeout(timeout, receiver).await {
+        Ok(Ok(_)) => claim_login_token(state, scene_id).await,
+        _ => Ok(None),
+    }
+}
+
+/// Query whether the scene is scanned, and get a one-time login token if so.
+///
+/// The token is issued only once per scan and can be exchanged for the open id
+/// at `/login/exchange`. With `?wait=n`, the request is held for up to n seconds
+/// (at most 60) until the scene is scanned instead of returning 404 immediately.
+async fn query_scene(
+    params: web::Path<(u32,)>,
+    query: web::Query<SceneQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let scene_id = params.0;
+    verify_scene_secret(&state, scene_id, &query.secret).await?;
+    let response = match query.wait.unwrap_or(0).min(MAX_WAIT_SECONDS) {
+        0 => claim_login_token(&state, scene_id).await?,
+        seconds => wait_login_token(&state, scene_id, Duration::from_secs(seconds)).await?,
+    };
 
     Ok(match response {
-        Some(open_id) => HttpResponse::Ok().json(json!({ "openID": open_id })),
+        Some(token) => HttpResponse::Ok().json(token),
         None => HttpResponse::NotFound().json(json!({})),
     })
 }
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    secret: String,
+}
+
+enum EventState {
+    Scanned(Value),
+    Waiting(oneshot::Receiver<String>, Instant),
+    Done,
+}
+
+fn sse_event(event: &str, data: Value) -> Bytes {
+    Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+/// Server-Sent Events of the scene.
+///
+/// Sends a `scanned` event with a login token once the scene is scanned, or an
+/// `expired` event when the scene expires, then closes the stream. Comments
+/// are sent periodically to keep the connection alive.
+async fn scene_events(
+    params: web::Path<(u32,)>,
+    query: web::Query<EventsQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let scene_id = params.0;
+    verify_scene_secret(&state, scene_id, &query.secret).await?;
+    let receiver = state.scene_notifier.wait(&scene_id.to_string());
+    let initial = match claim_login_token(&state, scene_id).await? {
+        Some(token) => EventState::Scanned(token),
+        None => EventState::Waiting(
+            receiver,
+            Instant::now() + Duration::from_secs(SCENE_EXPIRE_SECONDS),
+        ),
+    };
+
+    let events = stream::unfold(initial, move |event_state| {
+        let state = state.clone();
+        async move {
+            match event_state {
+                EventState::Done => None,
+                EventState::Scanned(token) => {
+                    Some((Ok(sse_event("scanned", token)), EventState::Done))
+                }
+                EventState::Waiting(mut receiver, deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Some((Ok(sse_event("expired", json!({}))), EventState::Done));
+                    }
+                    let tick = (deadline - now).min(Duration::from_secs(HEARTBEAT_SECONDS));
+                    match tokio::time::timeout(tick, &mut receiver).await {
+                        Err(_) => Some((
+                            Ok(Bytes::from_static(b": keep-alive\n\n")),
+                            EventState::Waiting(receiver, deadline),
+                        )),
+                        Ok(Ok(_)) => match claim_login_token(&state, scene_id).await {
+                            Ok(Some(token)) => {
+                                Some((Ok(sse_event("scanned", token)), EventState::Done))
+                            }
+                            // claimed by another request
+                            Ok(None) => None,
+                            Err(e) => Some((Err(e), EventState::Done)),
+                        },
+                        // notifier gone, nothing more will arrive
+                        Ok(Err(_)) => None,
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(events))
+}
@@ -0,0 +1,120 @@
+use crate::errors::Result;
+use crate::models;
+use uuid::Uuid;
+
+use diesel::prelude::*;
+
+pub fn insert_webhook(webhook: &models::NewWebhook, con: &PgConnection) -> Result<i64> {
+    use crate::schema::webhooks::dsl::*;
+    let new_id = diesel::insert_into(webhooks)
+        .values(webhook)
+        .returning(id)
+        .get_result(con)?;
+    Ok(new_id)
+}
+
+pub fn find_webhooks_by_receiver(
+    receiver: &str,
+    con: &PgConnection,
+) -> Result<Vec<models::Webhook>> {
+    use crate::schema::webhooks::dsl::*;
+    let found = webhooks
+        .filter(receiver_id.eq(receiver))
+        .order(id)
+        .load::<models::Webhook>(con)?;
+    Ok(found)
+}
+
+pub fn find_webhook(webhook_id: i64, con: &PgConnection) -> Result<Option<models::Webhook>> {
+    use crate::schema::webhooks::dsl::*;
+    let mut found = webhooks
+        .filter(id.eq(webhook_id))
+        .limit(1)
+        .load::<models::Webhook>(con)?;
+    Ok(found.pop())
+}
+
+/// Delete the webhook along with its deliveries.
+pub fn delete_webhook(webhook_id: i64, con: &PgConnection) -> Result<usize> {
+    use crate::schema::webhooks::dsl::*;
+    let deleted = diesel::delete(webhooks.filter(id.eq(webhook_id))).execute(con)?;
+    Ok(deleted)
+}
+
+pub fn insert_deliveries(
+    deliveries: &[models::NewWebhookDelivery],
+    con: &PgConnection,
+) -> Result<()> {
+    use crate::schema::webhook_deliveries::dsl::*;
+    if deliveries.is_empty() {
+        return Ok(());
+    }
+    diesel::insert_into(webhook_deliveries)
+        .values(deliveries)
+        .execute(con)?;
+    Ok(())
+}
+
+/// Claim at most `count` pending deliveries due at `now`, leased for `lease` seconds.
+pub fn claim_due_deliveries(
+    now: i64,
+    lease: i64,
+    count: i64,
+    con: &PgConnection,
+) -> Result<Vec<models::WebhookDelivery>> {
+    use crate::schema::webhook_deliveries::dsl::*;
+    con.transaction(|| {
+        let due = webhook_deliveries
+            .filter(status.eq("pending"))
+            .filter(next_time.le(now))
+            .order(next_time)
+            .limit(count)
+            .for_update()
+            .skip_locked()
+            .load::<models::WebhookDelivery>(con)?;
+        let ids: Vec<i64> = due.iter().map(|d| d.id).collect();
+        diesel::update(webhook_deliveries.filter(id.eq_any(ids)))
+            .set(next_time.eq(now + lease))
+            .execute(con)?;
+        Ok(due)
+    })
+}
+
+/// Save the result of a delivery attempt.
+pub fn update_delivery(delivery: &models::WebhookDelivery, con: &PgConnection) -> Result<()> {
+    use crate::schema::webhook_deliveries::dsl::*;
+    diesel::update(webhook_deliveries.filter(id.eq(delivery.id)))
+        .set((
+            status.eq(&delivery.status),
+            attempts.eq(delivery.attempts),
+            next_time.eq(delivery.next_time),
+            delivered_time.eq(delivery.delivered_time),
+            last_status_code.eq(delivery.last_status_code),
+            last_error.eq(&delivery.last_error),
+        ))
+        .execute(con)?;
+    Ok(())
+}
+
+/// Deliveries of a webhook, newest first.
+pub fn find_deliveries_by_webhook(
+    webhook: i64,
+    count: i64,
+    con: &PgConnection,
+) -> Result<Vec<models::WebhookDelivery>> {
+    use crate::schema::webhook_deliveries::dsl::*;
+    let found = webhook_deliveries
+        .filter(webhook_id.eq(webhook))
+        .order(id.desc())
+        .limit(count)
+        .load::<models::WebhookDelivery>(con)?;
+    Ok(found)
+}
+
+/// Delete deliveries of the messages, e.g. when they are purged.
+pub fn delete_deliveries_by_messages(messages: &[Uuid], con: &PgConnection) -> Result<usize> {
+    use crate::schema::webhook_deliveries::dsl::*;
+    let deleted =
+        diesel::delete(webhook_deliveries.filter(message_id.eq_any(messages))).execute(con)?;
+    Ok(deleted)
+}
@@ -0,0 +1,171 @@
+use crate::errors::{Error, Result};
+use crate::models::{NewWebhook, Webhook};
+use crate::rate_limit::{self, Bucket};
+use crate::shared_state::AppState;
+use crate::utils::unix_timestamp;
+use crate::webhook::{self, Event};
+use actix_web::{web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+const DEFAULT_DELIVERIES: i64 = 20;
+const MAX_DELIVERIES: i64 = 100;
+
+#[derive(Deserialize)]
+struct ReceiverQuery {
+    receiver: String,
+    sendkey: String,
+}
+
+#[derive(Deserialize)]
+struct NewWebhookForm {
+    receiver: String,
+    sendkey: String,
+    url: String,
+    /// comma separated events to deliver, all of them if absent
+    events: Option<String>,
+}
+
+/// Register a webhook for events of messages to the receiver, authorised by the receiver's send key.
+async fn create_webhook(
+    form: web::Form<NewWebhookForm>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let form = form.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &form.receiver, &form.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &form.sendkey).await?;
+    webhook::check_url("url", &form.url).await?;
+    let events = form
+        .events
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            Event::parse(s)
+                .map(|e| e.as_str().to_owned())
+                .ok_or_else(|| Error::BadRequest(format!("Unknown event {}", s)))
+        })
+        .collect::<Result<Vec<String>>>()?;
+    let webhook = NewWebhook {
+        receiver_id: form.receiver,
+        url: form.url,
+        events,
+        created_time: unix_timestamp(),
+    };
+
+    let max_webhooks = state.config.webhook.max_webhooks;
+    let id = web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        let registered = super::actions::find_webhooks_by_receiver(&webhook.receiver_id, &con)?;
+        if registered.len() as i64 >= max_webhooks {
+            return Err(Error::BadRequest(format!(
+                "At most {} webhooks can be registered",
+                max_webhooks
+            )));
+        }
+        super::actions::insert_webhook(&webhook, &con)
+    })
+    .await?;
+    log::info!("Webhook {} registered", id);
+    Ok(HttpResponse::Ok().json(json!({ "id": id })))
+}
+
+/// Webhooks registered for the receiver, authorised by the receiver's send key.
+async fn list_webhooks(
+    query: web::Query<ReceiverQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let query = query.into_inner();
+    crate::sendkey::verify(&state.config.secret_key, &query.receiver, &query.sendkey)?;
+    rate_limit::check(&state, Bucket::Sender, &query.sendkey).await?;
+    let receiver = query.receiver;
+    let webhooks = web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        super::actions::find_webhooks_by_receiver(&receiver, &con)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({ "webhooks": webhooks })))
+}
+
+#[derive(Deserialize)]
+struct SendkeyQuery {
+    sendkey: String,
+    /// max deliveries to list
+    limit: Option<i64>,
+}
+
+/// Find the webhook from database, authorised by the receiver's send key.
+async fn find_authorised_webhook(
+    id: i64,
+    sendkey: &str,
+    state: &web::Data<AppState>,
+) -> Result<Webhook> {
+    let db_state = state.clone();
+    let webhook = web::block(move || {
+        let con = db_state.as_ref().db_pool.get()?;
+        super::actions::find_webhook(id, &con)
+    })
+    .await?
+    .ok_or_else(|| Error::NotFound("Webhook not found".to_owned()))?;
+    crate::sendkey::verify(&state.config.secret_key, &webhook.receiver_id, sendkey)?;
+    rate_limit::check(state, Bucket::Sender, sendkey).await?;
+    Ok(webhook)
+}
+
+/// Delete a webhook along with its delivery log.
+async fn delete_webhook(
+    params: web::Path<(i64,)>,
+    query: web::Query<SendkeyQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let id = params.0;
+    find_authorised_webhook(id, &query.sendkey, &state).await?;
+    web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        super::actions::delete_webhook(id, &con)
+    })
+    .await?;
+    log::info!("Webhook {} deleted", id);
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+/// Delivery log of a webhook, newest first.
+async fn list_deliveries(
+    params: web::Path<(i64,)>,
+    query: web::Query<SendkeyQuery>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let id = params.0;
+    find_authorised_webhook(id, &query.sendkey, &state).await?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_DELIVERIES)
+        .clamp(1, MAX_DELIVERIES);
+    let deliveries = web::block(move || {
+        let con = state.as_ref().db_pool.get()?;
+        super::actions::find_deliveries_by_webhook(id, limit, &con)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(json!({ "deliveries": deliveries })))
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/webhooks")
+            .name("webhooks of receiver")
+            .route(web::get().to(list_webhooks))
+            .route(web::post().to(create_webhook)),
+    )
+    .service(
+        web::resource("/webhooks/{id}")
+            .name("webhook")
+            .route(web::delete().to(delete_webhook)),
+    )
+    .service(
+        web::resource("/webhooks/{id}/deliveries")
+            .name("webhook deliveries")
+            .route(web::get().to(list_deliveries)),
+    );
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use redis::Client as RedisClient;
+use tokio::sync::oneshot;
+
+const CHANNEL_PREFIX: &str = "wxpush:scene:";
+const RECONNECT_SECONDS: u64 = 1;
+
+/// The redis pub/sub channel a scan result for `scene_id` is published to.
+pub fn channel_name(scene_id: &str) -> String {
+    format!("{}{}", CHANNEL_PREFIX, scene_id)
+}
+
+type Waiters = Arc<Mutex<HashMap<String, Vec<oneshot::Sender<String>>>>>;
+
+/// Dispatches scene scan results published on redis to in-process waiters.
+///
+/// redis 0.15 has no async pub/sub, so a single blocking subscriber thread is
+/// run per server instance, pattern-subscribed to all scene channels. Since the
+/// callback publishes through redis, a scan handled by any instance wakes
+/// the waiters on every instance.
+pub struct SceneNotifier {
+    waiters: Waiters,
+}
+
+impl SceneNotifier {
+    pub fn start(client: RedisClient) -> Self {
+        let waiters = Waiters::default();
+        let thread_waiters = waiters.clone();
+        thread::Builder::new()
+            .name("scene-notifier".into())
+            .spawn(move || loop {
+                if let Err(e) = listen(&client, &thread_waiters) {
+                    log::warn!("Scene notifier disconnected from redis: {}", e);
+                }
+                thread::sleep(Duration::from_secs(RECONNECT_SECONDS));
+            })
+            .expect("Failed to spawn scene notifier thread.");
+        SceneNotifier { waiters }
+    }
+
+    /// Register interest in a scene, the receiver resolves with the open id once scanned.
+    ///
+    /// Register before checking the cached result in redis, so that a scan
+    /// happening in between is not missed.
+    pub fn wait(&self, scene_id: &str) -> oneshot::Receiver<String> {
+        let (sender, receiver) = oneshot::channel();
+        let mut waiters = self.waiters.lock().unwrap();
+        // drop waiters who gave up (timed out or disconnected)
+        waiters.retain(|_, senders| {
+            senders.retain(|s| !s.is_closed());
+            !senders.is_empty()
+        });
+        waiters.entry(scene_id.to_owned()).or_default().push(sender);
+        receiver
+    }
+}
+
+fn listen(client: &RedisClient, waiters: &Waiters) -> redis::RedisResult<()> {
+    let mut con = client.get_connection()?;
+    let mut pubsub = con.as_pubsub();
+    pubsub.psubscribe(format!("{}*", CHANNEL_PREFIX))?;
+    log::info!("Scene notifier subscribed to redis");
+    loop {
+        let msg = pubsub.get_message()?;
+        let open_id: String = msg.get_payload()?;
+        let scene_id = msg.get_channel_name().trim_start_matches(CHANNEL_PREFIX);
+        notify(waiters, scene_id, open_id);
+    }
+}
+
+fn notify(waiters: &Waiters, scene_id: &str, open_id: String) {
+    let senders = waiters.lock().unwrap().remove(scene_id);
+    if let Some(senders) = senders {
+        log::debug!("Waking {} waiter(s) of scene {}", senders.len(), scene_id);
+        for sender in senders {
+            // the waiter may have timed out already
+            let _ = sender.send(open_id.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_notify() {
+        let notifier = SceneNotifier {
+            waiters: Waiters::default(),
+        };
+        let first = notifier.wait("1");
+        let second = notifier.wait("1");
+        let other = notifier.wait("2");
+        notify(&notifier.waiters, "1", "open_id".to_owned());
+        assert_eq!(first.await.unwrap(), "open_id");
+        assert_eq!(second.await.unwrap(), "open_id");
+        // dropped waiters are pruned on next wait
+        drop(other);
+        let _third = notifier.wait("3");
+        assert!(!notifier.waiters.lock().unwrap().contains_key("2"));
+    }
+}
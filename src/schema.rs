@@ -10,5 +10,150 @@ table! {
         created_time -> Int8,
         ip -> Text,
         UA -> Text,
+        format -> Text,
+        expires_at -> Nullable<Int8>,
+        revoked_time -> Nullable<Int8>,
+        acked_time -> Nullable<Int8>,
+        acked_by -> Nullable<Text>,
+        ack_webhook -> Nullable<Text>,
+        wechat_msg_id -> Nullable<Int8>,
+        dedup_key -> Nullable<Text>,
+        dedup_until -> Nullable<Int8>,
+        duplicates -> Int4,
+        dedup_closed -> Bool,
+        queued -> Bool,
+        priority -> Text,
+        held_until -> Nullable<Int8>,
+        digest_id -> Nullable<Uuid>,
+        channel -> Nullable<Text>,
+        queue_lease -> Nullable<Int8>,
+        sender_id -> Nullable<Text>,
     }
 }
+
+table! {
+    message_views (id) {
+        id -> Int8,
+        message_id -> Uuid,
+        viewed_time -> Int8,
+        client -> Text,
+    }
+}
+
+table! {
+    escalations (id) {
+        id -> Int8,
+        message_id -> Uuid,
+        receivers -> Array<Text>,
+        step -> Int4,
+        max_steps -> Int4,
+        interval_seconds -> Int8,
+        next_time -> Int8,
+        status -> Text,
+    }
+}
+
+table! {
+    webhooks (id) {
+        id -> Int8,
+        receiver_id -> Text,
+        url -> Text,
+        events -> Array<Text>,
+        created_time -> Int8,
+    }
+}
+
+table! {
+    webhook_deliveries (id) {
+        id -> Int8,
+        webhook_id -> Nullable<Int8>,
+        receiver_id -> Text,
+        message_id -> Uuid,
+        url -> Text,
+        event -> Text,
+        payload -> Text,
+        status -> Text,
+        attempts -> Int4,
+        next_time -> Int8,
+        created_time -> Int8,
+        delivered_time -> Nullable<Int8>,
+        last_status_code -> Nullable<Int4>,
+        last_error -> Nullable<Text>,
+    }
+}
+
+table! {
+    receiver_preferences (receiver_id) {
+        receiver_id -> Text,
+        timezone -> Text,
+        quiet_start -> Nullable<Int4>,
+        quiet_end -> Nullable<Int4>,
+        min_priority -> Text,
+        updated_time -> Int8,
+        digest_interval -> Nullable<Int4>,
+    }
+}
+
+table! {
+    digests (id) {
+        id -> Uuid,
+        receiver_id -> Text,
+        created_time -> Int8,
+    }
+}
+
+joinable!(message_views -> messages (message_id));
+joinable!(escalations -> messages (message_id));
+joinable!(webhook_deliveries -> webhooks (webhook_id));
+joinable!(messages -> digests (digest_id));
+
+table! {
+    receiver_accounts (receiver_id) {
+        receiver_id -> Text,
+        account -> Text,
+        updated_time -> Int8,
+        sendkey -> Nullable<Text>,
+    }
+}
+
+table! {
+    hook_mappings (receiver_id, name) {
+        receiver_id -> Text,
+        name -> Text,
+        mapping -> Text,
+        updated_time -> Int8,
+    }
+}
+
+table! {
+    receiver_emails (receiver_id) {
+        receiver_id -> Text,
+        address -> Text,
+        always -> Bool,
+        verified_time -> Int8,
+    }
+}
+
+table! {
+    delivery_channels (receiver_id, position) {
+        receiver_id -> Text,
+        position -> Int4,
+        kind -> Text,
+        account -> Nullable<Text>,
+        target -> Nullable<Text>,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(
+    delivery_channels,
+    digests,
+    escalations,
+    hook_mappings,
+    message_views,
+    messages,
+    receiver_accounts,
+    receiver_emails,
+    receiver_preferences,
+    webhook_deliveries,
+    webhooks,
+);
@@ -0,0 +1,47 @@
+//! Send keys authorise access to the messages of a receiver.
+//!
+//! A send key is derived from the receiver's open id with the server secret
+//! key, so it needs no storage and can be handed out again at any login.
+//! Only its hash is saved, to find the receiver of a send key in the url.
+use crate::errors::{Error, Result};
+use crate::utils::{hmac_sha256_hex, secure_eq, sha256_hex};
+
+pub fn sendkey(secret_key: &str, receiver: &str) -> String {
+    hmac_sha256_hex(secret_key, &format!("sendkey:{}", receiver))
+}
+
+/// The hash of the send key saved along with the receiver's account.
+pub fn hash(key: &str) -> String {
+    sha256_hex(key)
+}
+
+pub fn verify(secret_key: &str, receiver: &str, key: &str) -> Result<()> {
+    match secure_eq(&sendkey(secret_key, receiver), key) {
+        true => Ok(()),
+        false => Err(Error::Unauthorized("Invalid sendkey".to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sendkey() {
+        let key = sendkey("secret", "open_id");
+        assert!(verify("secret", "open_id", &key).is_ok());
+        assert!(verify("secret", "other_open_id", &key).is_err());
+        assert!(verify("other_secret", "open_id", &key).is_err());
+    }
+
+    #[test]
+    fn test_hash() {
+        let key = sendkey("secret", "open_id");
+        assert_eq!(hash(&key), hash(&key));
+        assert_ne!(hash(&key), key);
+        assert_eq!(
+            hash("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
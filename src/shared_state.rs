@@ -1,4 +1,5 @@
-use std::sync::Mutex;
+use futures::lock::Mutex;
+use std::collections::HashMap;
 
 // redis
 use redis::Client as RedisClient;
@@ -6,26 +7,41 @@ use redis::Client as RedisClient;
 use diesel::prelude::PgConnection;
 use diesel::r2d2::ConnectionManager;
 
-use crate::config::Config;
+use crate::config::{Config, DEFAULT_ACCOUNT};
 use crate::errors::Error;
-use crate::wechat::TokenManager;
+use crate::scene_notifier::SceneNotifier;
+use crate::wechat::{TokenManager, TokenSource};
 
 pub struct AppState {
-    pub token_manager: Mutex<TokenManager>,
+    /// by account name
+    pub token_managers: HashMap<String, Mutex<TokenManager>>,
     pub redis: RedisClient,
     pub db_pool: r2d2::Pool<ConnectionManager<PgConnection>>,
+    pub scene_notifier: SceneNotifier,
 
     pub config: Config,
 }
 
 impl AppState {
     pub fn from_config(config: Config) -> Self {
-        let token_manager = TokenManager::new(
-            config.wechat.app_id.clone(),
-            config.wechat.app_secret.clone(),
-        );
+        let accounts = std::iter::once((DEFAULT_ACCOUNT, &config.wechat))
+            .chain(config.accounts.iter().map(|(name, a)| (name.as_str(), a)))
+            .map(|(name, account)| {
+                let manager = TokenManager::new(account.app_id.clone(), account.app_secret.clone());
+                (name.to_owned(), Mutex::new(manager))
+            });
+        let wecom_apps = config.wecom.iter().map(|(name, app)| {
+            let manager = TokenManager::with_source(
+                TokenSource::Wecom,
+                app.corp_id.clone(),
+                app.corp_secret.clone(),
+            );
+            (name.to_owned(), Mutex::new(manager))
+        });
+        let token_managers = accounts.chain(wecom_apps).collect();
         // redis
         let redis_client = RedisClient::open(config.redis_url.clone()).unwrap();
+        let scene_notifier = SceneNotifier::start(redis_client.clone());
         // sql
         let db_manager = ConnectionManager::<PgConnection>::new(&config.postgres_url);
         let db_pool = r2d2::Pool::builder()
@@ -35,13 +51,21 @@ impl AppState {
 
         // return app state
         AppState {
-            token_manager: Mutex::new(token_manager),
+            token_managers,
             redis: redis_client,
             db_pool,
+            scene_notifier,
             config,
         }
     }
 
+    /// The token manager of the account.
+    pub fn token_manager(&self, account: &str) -> Result<&Mutex<TokenManager>, Error> {
+        self.token_managers
+            .get(account)
+            .ok_or_else(|| Error::BadRequest(format!("Unknown account {}", account)))
+    }
+
     pub async fn redis_connection(&self) -> Result<redis::aio::Connection, Error> {
         Ok(self.redis.get_async_connection().await?)
     }
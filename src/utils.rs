@@ -1,6 +1,8 @@
 use crate::errors::Result;
 use actix_web::HttpRequest;
 use failure::ResultExt;
+use std::net::IpAddr;
+use std::time::SystemTime;
 
 pub fn get_user_agent(request: &HttpRequest) -> Result<String> {
     let header_value = request.headers().get(actix_web::http::header::USER_AGENT);
@@ -12,3 +14,164 @@ pub fn get_user_agent(request: &HttpRequest) -> Result<String> {
             .to_owned()),
     }
 }
+
+/// Seconds since unix epoch.
+pub fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Coarse client type from the user agent: wechat, mobile, desktop or other.
+pub fn client_type(user_agent: &str) -> &'static str {
+    if user_agent.contains("MicroMessenger") {
+        "wechat"
+    } else if user_agent.contains("Mobile") || user_agent.contains("Android") {
+        "mobile"
+    } else if user_agent.contains("Windows")
+        || user_agent.contains("Macintosh")
+        || user_agent.contains("X11")
+    {
+        "desktop"
+    } else {
+        "other"
+    }
+}
+
+/// The client of a request through the proxies, the rightmost untrusted one in `X-Forwarded-For`.
+fn forwarded_client(header: &str, trusted_proxies: &[IpAddr]) -> Option<IpAddr> {
+    for hop in header.rsplit(',') {
+        let ip = hop.trim().parse::<IpAddr>().ok()?;
+        if !trusted_proxies.contains(&ip) {
+            return Some(ip);
+        }
+    }
+    None
+}
+
+/// Ip address of the client, without the port.
+///
+/// Forwarding headers are only trusted from the proxies, as anyone can send them.
+pub fn client_ip(request: &HttpRequest, trusted_proxies: &[IpAddr]) -> String {
+    let peer = match request.peer_addr() {
+        Some(addr) => addr.ip(),
+        None => return String::new(),
+    };
+    if !trusted_proxies.contains(&peer) {
+        return peer.to_string();
+    }
+    request
+        .headers()
+        .get("X-Forwarded-For")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|header| forwarded_client(header, trusted_proxies))
+        .unwrap_or(peer)
+        .to_string()
+}
+
+/// Whether the client prefers an html page, e.g. a browser.
+pub fn accepts_html(request: &HttpRequest) -> bool {
+    request
+        .headers()
+        .get(actix_web::http::header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|accept| accept.contains("text/html"))
+        .unwrap_or(false)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Random hex string with `bytes` bytes of entropy, e.g. for secrets and nonces.
+pub fn random_hex(bytes: usize) -> String {
+    let random: Vec<u8> = (0..bytes).map(|_| rand::random::<u8>()).collect();
+    to_hex(&random)
+}
+
+/// HMAC-SHA256 of data, in hex.
+pub fn hmac_sha256_hex(key: &str, data: &str) -> String {
+    use crypto::hmac::Hmac;
+    use crypto::mac::Mac;
+    use crypto::sha2::Sha256;
+    let mut hmac = Hmac::new(Sha256::new(), key.as_bytes());
+    hmac.input(data.as_bytes());
+    to_hex(hmac.result().code())
+}
+
+/// SHA256 of data, in hex.
+pub fn sha256_hex(data: &str) -> String {
+    use crypto::digest::Digest;
+    use crypto::sha2::Sha256;
+    let mut sha = Sha256::new();
+    sha.input_str(data);
+    sha.result_str()
+}
+
+/// Compare secrets in constant time.
+pub fn secure_eq(lhs: &str, rhs: &str) -> bool {
+    crypto::util::fixed_time_eq(lhs.as_bytes(), rhs.as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_client_type() {
+        let wechat = "Mozilla/5.0 (iPhone; CPU iPhone OS 13_5 like Mac OS X) AppleWebKit/605.1.15 \
+                      (KHTML, like Gecko) Mobile/15E148 MicroMessenger/7.0.12(0x17000c2d) NetType/WIFI";
+        assert_eq!(client_type(wechat), "wechat");
+        let android = "Mozilla/5.0 (Linux; Android 10; Pixel 3) AppleWebKit/537.36 \
+                       (KHTML, like Gecko) Chrome/83.0.4103.106 Safari/537.36";
+        assert_eq!(client_type(android), "mobile");
+        let iphone = "Mozilla/5.0 (iPhone; CPU iPhone OS 13_5 like Mac OS X) AppleWebKit/605.1.15 \
+                      (KHTML, like Gecko) Version/13.1.1 Mobile/15E148 Safari/604.1";
+        assert_eq!(client_type(iphone), "mobile");
+        let mac = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_5) AppleWebKit/605.1.15 \
+                   (KHTML, like Gecko) Version/13.1.1 Safari/605.1.15";
+        assert_eq!(client_type(mac), "desktop");
+        assert_eq!(client_type("curl/7.68.0"), "other");
+        assert_eq!(client_type(""), "other");
+    }
+
+    #[test]
+    fn test_client_ip() {
+        use actix_web::test::TestRequest;
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+        let trusted = [proxy];
+        // forwarding headers from anyone else are ignored
+        let request = TestRequest::default()
+            .peer_addr("203.0.113.7:5000".parse().unwrap())
+            .header("X-Forwarded-For", "198.51.100.1")
+            .to_http_request();
+        assert_eq!(client_ip(&request, &trusted), "203.0.113.7");
+        assert_eq!(client_ip(&request, &[]), "203.0.113.7");
+        // the client may prepend anything, the proxy appends the real one
+        let request = TestRequest::default()
+            .peer_addr("10.0.0.2:5000".parse().unwrap())
+            .header("X-Forwarded-For", "198.51.100.1, 203.0.113.7")
+            .to_http_request();
+        assert_eq!(client_ip(&request, &trusted), "203.0.113.7");
+        // without the header, the proxy is the client
+        let request = TestRequest::default()
+            .peer_addr("10.0.0.2:5000".parse().unwrap())
+            .to_http_request();
+        assert_eq!(client_ip(&request, &trusted), "10.0.0.2");
+        assert_eq!(
+            client_ip(&TestRequest::default().to_http_request(), &[]),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_forwarded_client() {
+        let trusted: Vec<IpAddr> = vec!["10.0.0.2".parse().unwrap(), "10.0.0.3".parse().unwrap()];
+        let client = |header| forwarded_client(header, &trusted).map(|ip| ip.to_string());
+        assert_eq!(client("1.2.3.4, 10.0.0.3"), Some("1.2.3.4".to_owned()));
+        assert_eq!(client("5.6.7.8,1.2.3.4"), Some("1.2.3.4".to_owned()));
+        assert_eq!(client("10.0.0.2, 10.0.0.3"), None);
+        assert_eq!(client("garbage, 10.0.0.3"), None);
+    }
+}
@@ -0,0 +1,315 @@
+//! Outgoing webhooks notifying senders of what happened to their messages.
+//!
+//! Events are queued in the database and delivered by `jobs::webhook` with
+//! retries. The JSON payload is signed with the receiver's send key, which the
+//! sender already holds, as the hex HMAC-SHA256 of the request body in a header.
+//!
+//! Urls are given by senders, so only public hosts are posted to, lest the
+//! server is made to reach internal services.
+use actix_web::web;
+use serde_json::{json, Value};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use url::{Host, Url};
+use uuid::Uuid;
+
+use crate::errors::Error;
+use crate::models::NewWebhookDelivery;
+use crate::routes::webhook::actions;
+use crate::shared_state::AppState;
+use crate::utils::{hmac_sha256_hex, unix_timestamp};
+
+pub const SIGNATURE_HEADER: &str = "X-Wxpush-Signature";
+
+/// Lifecycle events of a message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// pushed to wechat
+    Sent,
+    /// rejected by wechat, or not delivered to the receiver
+    Failed,
+    /// delivered to the receiver, reported by wechat
+    Delivered,
+    /// the detail page is opened for the first time
+    Viewed,
+    Acknowledged,
+}
+
+impl Event {
+    pub const ALL: [Event; 5] = [
+        Event::Sent,
+        Event::Failed,
+        Event::Delivered,
+        Event::Viewed,
+        Event::Acknowledged,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Event::Sent => "sent",
+            Event::Failed => "failed",
+            Event::Delivered => "delivered",
+            Event::Viewed => "viewed",
+            Event::Acknowledged => "acknowledged",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Event::ALL.iter().copied().find(|e| e.as_str() == s)
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum WebhookError {
+    #[fail(display = "Not a http(s) url")]
+    BadUrl,
+
+    #[fail(display = "Failed to resolve {}", _0)]
+    Unresolved(String),
+
+    #[fail(display = "{} is not a public host", _0)]
+    Internal(String),
+
+    #[fail(display = "Request failed: {}", _0)]
+    Http(#[fail(cause)] reqwest::Error),
+}
+
+impl From<reqwest::Error> for WebhookError {
+    fn from(e: reqwest::Error) -> Self {
+        WebhookError::Http(e)
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // this network, shared address space and reserved
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    if let Some(v4) = ip.to_ipv4() {
+        return is_public_v4(v4);
+    }
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // unique local and link local
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80)
+}
+
+/// Whether the address is on the public internet, i.e. not loopback, private, link local and alike.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn parse_url(url: &str) -> Result<Url, WebhookError> {
+    let url = Url::parse(url.trim()).map_err(|_| WebhookError::BadUrl)?;
+    match (url.scheme(), url.host()) {
+        ("http", Some(_)) | ("https", Some(_)) => Ok(url),
+        _ => Err(WebhookError::BadUrl),
+    }
+}
+
+/// Parse a http(s) url and resolve its host, failing unless all its addresses are public.
+pub async fn resolve(url: &str) -> Result<(Url, SocketAddr), WebhookError> {
+    let url = parse_url(url)?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let addrs: Vec<SocketAddr> = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+        Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|_| WebhookError::Unresolved(domain.to_owned()))?
+            .collect(),
+        None => return Err(WebhookError::BadUrl),
+    };
+    let host = url.host_str().unwrap_or_default().to_owned();
+    match addrs.first() {
+        None => Err(WebhookError::Unresolved(host)),
+        Some(_) if !addrs.iter().all(|addr| is_public(addr.ip())) => {
+            Err(WebhookError::Internal(host))
+        }
+        Some(addr) => Ok((url, *addr)),
+    }
+}
+
+/// Check a url given by a sender to be posted to, as `name` in the error.
+pub async fn check_url(name: &str, url: &str) -> crate::errors::Result<()> {
+    match resolve(url).await {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::BadRequest(format!("Bad {}: {}", name, e))),
+    }
+}
+
+/// POST the payload to the url, returns the response status.
+///
+/// The host is checked right before posting, and plain http requests go to
+/// the checked address. Redirects are not followed.
+pub async fn post(
+    url: &str,
+    sendkey: &str,
+    payload: &str,
+    timeout: Duration,
+) -> Result<reqwest::StatusCode, WebhookError> {
+    let (mut url, addr) = resolve(url).await?;
+    let client = reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+    // https is verified against the host name, so it can't be pointed elsewhere
+    let mut host = None;
+    if url.scheme() == "http" {
+        host = Some(match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_owned(),
+        });
+        url.set_ip_host(addr.ip())
+            .map_err(|_| WebhookError::BadUrl)?;
+    }
+    let mut request = client.post(url.as_str());
+    if let Some(host) = host {
+        request = request.header(reqwest::header::HOST, host);
+    }
+    let response = request
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, hmac_sha256_hex(sendkey, payload))
+        .body(payload.to_owned())
+        .send()
+        .await?;
+    Ok(response.status())
+}
+
+/// Queue an event of a message for the webhooks registered for its receiver,
+/// and for `extra_url` if any.
+///
+/// `data` is merged into the payload. Failures are logged, never returned, as
+/// the event has happened anyway.
+pub async fn emit(
+    state: &web::Data<AppState>,
+    event: Event,
+    receiver: &str,
+    message_id: Uuid,
+    data: Value,
+    extra_url: Option<String>,
+) {
+    let now = unix_timestamp();
+    let mut payload = json!({
+        "event": event.as_str(),
+        "token": message_id,
+        "receiver": receiver,
+        "time": now,
+    });
+    if let (Some(payload), Value::Object(data)) = (payload.as_object_mut(), data) {
+        payload.extend(data);
+    }
+    let payload = payload.to_string();
+    let receiver = receiver.to_owned();
+    let db_state = state.clone();
+    let result = web::block(move || {
+        let con = db_state.db_pool.get()?;
+        let webhooks = actions::find_webhooks_by_receiver(&receiver, &con)?;
+        let delivery = |webhook_id, url| NewWebhookDelivery {
+            webhook_id,
+            receiver_id: receiver.clone(),
+            message_id,
+            url,
+            event: event.as_str().to_owned(),
+            payload: payload.clone(),
+            next_time: now,
+            created_time: now,
+        };
+        let mut deliveries: Vec<NewWebhookDelivery> = webhooks
+            .into_iter()
+            .filter(|w| w.events.is_empty() || w.events.iter().any(|e| e == event.as_str()))
+            .map(|w| delivery(Some(w.id), w.url))
+            .collect();
+        deliveries.extend(extra_url.map(|url| delivery(None, url)));
+        actions::insert_deliveries(&deliveries, &con)
+    })
+    .await;
+    if let Err(e) = result {
+        log::warn!(
+            "Failed to queue {} event of message {}: {}",
+            event.as_str(),
+            message_id,
+            Error::from(e)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_event() {
+        for event in Event::ALL.iter() {
+            assert_eq!(Event::parse(event.as_str()), Some(*event));
+        }
+        assert_eq!(Event::parse("unknown"), None);
+    }
+
+    #[test]
+    fn test_is_public() {
+        for ip in &["8.8.8.8", "93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(is_public(ip.parse().unwrap()), "{}", ip);
+        }
+        let internal = [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::ffff:127.0.0.1",
+            "fd00::1",
+            "fe80::1",
+        ];
+        for ip in internal.iter() {
+            assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        assert!(matches!(
+            resolve("javascript:alert(1)").await,
+            Err(WebhookError::BadUrl)
+        ));
+        assert!(matches!(
+            resolve("file:///etc/passwd").await,
+            Err(WebhookError::BadUrl)
+        ));
+        for url in &[
+            "http://127.0.0.1:6379/",
+            "http://169.254.169.254/latest/meta-data",
+            "https://[::1]/",
+            "http://10.0.0.1/hook",
+        ] {
+            assert!(
+                matches!(resolve(url).await, Err(WebhookError::Internal(_))),
+                "{}",
+                url
+            );
+        }
+        let (url, addr) = resolve("https://93.184.216.34/hook").await.unwrap();
+        assert_eq!(url.path(), "/hook");
+        assert_eq!(addr, "93.184.216.34:443".parse().unwrap());
+    }
+}
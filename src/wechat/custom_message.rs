@@ -0,0 +1,41 @@
+//! Customer service messages, which reach only users who interacted with the account in 48 hours.
+//!
+//! [wechat API]: https://developers.weixin.qq.com/doc/offiaccount/Message_Management/Service_Center_messages.html
+use super::errors::WechatError;
+use super::template_message::NewMessage;
+use super::Request;
+use super::TokenManager;
+
+use futures::lock::Mutex;
+use serde_json::{json, Value};
+
+/// Send the message as a text message linking to its detail page.
+pub async fn send_text_message(
+    token_manager: &Mutex<TokenManager>,
+    message: &NewMessage,
+) -> Result<Value, WechatError> {
+    const URL: &str = "https://api.weixin.qq.com/cgi-bin/message/custom/send";
+    let summary = message
+        .summary
+        .as_ref()
+        .or(message.body.as_ref())
+        .map(String::as_str)
+        .unwrap_or_default();
+    let mut content = format!("{}\n\n{}", message.title, summary);
+    if let Some(url) = &message.detail_url {
+        content.push_str(&format!("\n\n<a href=\"{}\">查看详情</a>", url));
+    }
+    let data = json!({
+        "touser": message.receiver,
+        "msgtype": "text",
+        "text": { "content": content },
+    });
+    log::trace!("Sending data to wechat: {}", data);
+
+    let mut request = Request::post(URL).data(&data);
+    {
+        let mut guard = token_manager.lock().await;
+        request = request.sign(&mut guard).await?;
+    }
+    request.send().await
+}
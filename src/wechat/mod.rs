@@ -6,10 +6,13 @@ pub use request::Request;
 
 // token management
 mod token;
-pub use token::TokenManager;
+pub use token::{TokenManager, TokenSource};
 
 // other mods
+pub mod custom_message;
 
 // generate qrcode
 pub mod qrcode;
+pub mod quota;
 pub mod template_message;
+pub mod wecom;
@@ -2,9 +2,9 @@ use super::errors::WechatError;
 use super::Request;
 use super::TokenManager;
 
+use futures::lock::Mutex;
 use rand;
 use serde_json::{json, Value};
-use std::sync::Mutex;
 
 pub async fn create_new_temp(
     token_manager: &Mutex<TokenManager>,
@@ -26,7 +26,7 @@ pub async fn create_new_temp(
 
     let mut request = Request::post(URL).data(&data);
     {
-        let mut guard = token_manager.lock().unwrap();
+        let mut guard = token_manager.lock().await;
         request = request.sign(&mut guard).await?;
     }
     let mut resp = request.send().await?;
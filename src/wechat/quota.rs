@@ -0,0 +1,21 @@
+use super::errors::WechatError;
+use super::Request;
+use super::TokenManager;
+
+use futures::lock::Mutex;
+use serde_json::{json, Value};
+
+/// Reset the daily api call quota of the account, which itself can be called 10 times a month.
+pub async fn clear_quota(
+    token_manager: &Mutex<TokenManager>,
+    app_id: &str,
+) -> Result<Value, WechatError> {
+    const URL: &str = "https://api.weixin.qq.com/cgi-bin/clear_quota";
+    let data = json!({ "appid": app_id });
+    let mut request = Request::post(URL).data(&data);
+    {
+        let mut guard = token_manager.lock().await;
+        request = request.sign(&mut guard).await?;
+    }
+    request.send().await
+}
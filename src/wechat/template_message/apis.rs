@@ -1,7 +1,7 @@
 use super::super::{errors::WechatError, Request, TokenManager};
 use super::NewMessage;
+use futures::lock::Mutex;
 use serde_json::{json, Value};
-use std::sync::Mutex;
 
 pub async fn send_template_message(
     token_manager: &Mutex<TokenManager>,
@@ -17,7 +17,7 @@ pub async fn send_template_message(
                 "value": message.title
             },
             "body": {
-                "value": message.body.as_ref().unwrap_or(&"".to_owned())
+                "value": message.summary.as_ref().or(message.body.as_ref()).unwrap_or(&"".to_owned())
             }
         },
     });
@@ -25,7 +25,7 @@ pub async fn send_template_message(
 
     let mut request = Request::post(URL).data(&data);
     {
-        let mut guard = token_manager.lock().unwrap();
+        let mut guard = token_manager.lock().await;
         request = request.sign(&mut guard).await?;
     }
     let response = request.send().await?;
@@ -1,13 +1,41 @@
 use serde::{Deserialize, Serialize};
 
+use crate::html::body::BodyFormat;
+use crate::preferences::Priority;
+
 /// The form parsed directly from web request
 ///
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct NewMessage {
     pub receiver: String,
+    /// name of the official account to push by, defaults to the one the receiver subscribed to
+    pub account: Option<String>,
     pub title: String,
     pub body: Option<String>,
     pub url: Option<String>,
+    /// text (default), markdown or html
+    pub format: Option<BodyFormat>,
+    /// low, normal (default), high or critical, others may be held in quiet hours or for digest mode
+    pub priority: Option<Priority>,
+    /// unix timestamp after which the message is gone
+    pub expires_at: Option<i64>,
+    /// resend the message if not viewed within this many minutes
+    pub escalate_after: Option<u64>,
+    /// comma separated open ids to escalate to after resending to the receiver
+    pub escalate_to: Option<String>,
+    /// max times to resend, defaults to once to the receiver and once to each of `escalate_to`
+    pub escalate_steps: Option<i32>,
+    /// url to POST to when the message is acknowledged
+    pub ack_webhook: Option<String>,
+    /// identical messages with this key within the window are pushed only once
+    pub dedup_key: Option<String>,
+    /// minutes of the dedup window
+    pub dedup_window: Option<u64>,
+    /// same as the `Idempotency-Key` header
+    pub idempotency_key: Option<String>,
+    /// send key of the receiver, identifying the sender of the message
+    #[serde(skip_serializing)]
+    pub sendkey: Option<String>,
     // template_id should be replace with default template id before
     // passing to wechat module
     pub template_id: Option<String>,
@@ -17,4 +45,6 @@ pub struct NewMessage {
     pub id: Option<uuid::Uuid>,
     // the detail url pushed to wechat which receiver will open to see the detailed message
     pub detail_url: Option<String>,
+    // plain text summary of the body pushed in the template message
+    pub summary: Option<String>,
 }
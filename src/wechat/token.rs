@@ -28,21 +28,44 @@ impl Token {
     }
 }
 
+/// Where access tokens are applied for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenSource {
+    /// `cgi-bin/token` of official accounts
+    OfficialAccount,
+    /// `cgi-bin/gettoken` of WeCom apps, with the corp id and the app secret
+    Wecom,
+}
+
 pub struct TokenManager {
+    source: TokenSource,
     app_id: String,
     app_secret: String,
     token: Option<Token>,
+    /// number of tokens applied for since last taken, to track the api quota
+    issued: u64,
 }
 
 impl TokenManager {
     pub fn new(app_id: String, app_secret: String) -> Self {
+        TokenManager::with_source(TokenSource::OfficialAccount, app_id, app_secret)
+    }
+
+    pub fn with_source(source: TokenSource, app_id: String, app_secret: String) -> Self {
         TokenManager {
+            source,
             app_id,
             app_secret,
             token: None,
+            issued: 0,
         }
     }
 
+    /// Number of tokens applied for since last called.
+    pub fn take_issued(&mut self) -> u64 {
+        std::mem::take(&mut self.issued)
+    }
+
     /// Get access token, apply one if current one is no good
     pub async fn get_access_token<'a>(&'a mut self) -> Result<Token, WechatError> {
         match &self.token {
@@ -58,16 +81,28 @@ impl TokenManager {
     async fn apply_new_token(&mut self) -> Result<Token, WechatError> {
         log::debug!("Applying for a new access token");
 
-        const URL: &'static str = "https://api.weixin.qq.com/cgi-bin/token";
-        let params = json!({
-            "grant_type": "client_credential",
-            "appid": self.app_id.as_str(),
-            "secret": self.app_secret.as_str(),
-        });
+        let (url, params) = match self.source {
+            TokenSource::OfficialAccount => (
+                "https://api.weixin.qq.com/cgi-bin/token",
+                json!({
+                    "grant_type": "client_credential",
+                    "appid": self.app_id.as_str(),
+                    "secret": self.app_secret.as_str(),
+                }),
+            ),
+            TokenSource::Wecom => (
+                "https://qyapi.weixin.qq.com/cgi-bin/gettoken",
+                json!({
+                    "corpid": self.app_id.as_str(),
+                    "corpsecret": self.app_secret.as_str(),
+                }),
+            ),
+        };
 
         // form request
-        let request = Request::get(URL).data(&params);
+        let request = Request::get(url).data(&params);
         //
+        self.issued += 1;
         let data = request.send().await?;
 
         TokenManager::parse_response_body(data).map(|token| {
@@ -0,0 +1,107 @@
+//! Application messages of WeCom (Enterprise WeChat) self-built apps.
+//!
+//! [WeCom API]: https://developer.work.weixin.qq.com/document/path/90236
+use super::errors::WechatError;
+use super::template_message::NewMessage;
+use super::Request;
+use super::TokenManager;
+
+use futures::lock::Mutex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// errcode of WeCom when none of the receivers is a valid user of the app
+pub const INVALID_USER: u64 = 81013;
+
+/// Type of the application message.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageType {
+    Text,
+    /// a card linking to the detail page
+    #[default]
+    TextCard,
+    Markdown,
+}
+
+fn message_data(message_type: MessageType, message: &NewMessage) -> Value {
+    let summary = message
+        .summary
+        .as_ref()
+        .or(message.body.as_ref())
+        .map(String::as_str)
+        .unwrap_or_default();
+    let url = message.detail_url.as_deref().unwrap_or_default();
+    match message_type {
+        MessageType::Text => json!({
+            "msgtype": "text",
+            "text": {
+                "content": format!("{}\n\n{}\n\n{}", message.title, summary, url)
+            }
+        }),
+        MessageType::TextCard => json!({
+            "msgtype": "textcard",
+            "textcard": {
+                "title": message.title,
+                // an empty description is rejected
+                "description": if summary.is_empty() { &message.title } else { summary },
+                "url": url,
+                "btntxt": "详情"
+            }
+        }),
+        MessageType::Markdown => json!({
+            "msgtype": "markdown",
+            "markdown": {
+                "content": format!("**{}**\n{}\n[查看详情]({})", message.title, summary, url)
+            }
+        }),
+    }
+}
+
+/// Send an application message to the WeCom user id in `message.receiver`.
+pub async fn send_message(
+    token_manager: &Mutex<TokenManager>,
+    agent_id: i64,
+    message_type: MessageType,
+    message: &NewMessage,
+) -> Result<Value, WechatError> {
+    const URL: &str = "https://qyapi.weixin.qq.com/cgi-bin/message/send";
+    let mut data = message_data(message_type, message);
+    data["touser"] = json!(message.receiver);
+    data["agentid"] = json!(agent_id);
+    log::trace!("Sending data to WeCom: {}", data);
+
+    let mut request = Request::post(URL).data(&data);
+    {
+        let mut guard = token_manager.lock().await;
+        request = request.sign(&mut guard).await?;
+    }
+    let response = request.send().await?;
+    // a partly failed message still succeeds, with the failed receivers listed
+    match response["invaliduser"].as_str() {
+        Some(invalid) if !invalid.is_empty() => Err(WechatError::Wechat {
+            errcode: INVALID_USER,
+            errmsg: format!("invalid user {}", invalid),
+        }),
+        _ => Ok(response),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_message_data() {
+        let message = NewMessage {
+            title: "title".to_owned(),
+            detail_url: Some("https://example.com/detail".to_owned()),
+            ..NewMessage::default()
+        };
+        let card = message_data(MessageType::TextCard, &message);
+        assert_eq!(card["textcard"]["description"], "title");
+        assert_eq!(card["textcard"]["url"], "https://example.com/detail");
+        let text = message_data(MessageType::Text, &message);
+        assert_eq!(text["msgtype"], "text");
+    }
+}
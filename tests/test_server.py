@@ -20,6 +20,51 @@ class TestCase(unittest.TestCase):
     def post(self, path, *args, **kws):
         return self.client.post(self.url(path), *args, **kws)
 
+    def put(self, path, *args, **kws):
+        return self.client.put(self.url(path), *args, **kws)
+
+    def delete(self, path, *args, **kws):
+        return self.client.delete(self.url(path), *args, **kws)
+
+    def login(self, open_id=None):
+        """Subscribe by a scene scan and log in, returns the open id and send key."""
+        if open_id is None:
+            open_id = f'test_{uuid.uuid4().hex}'
+        r = self.post('/scene')
+        assert r.status_code == 200
+        scene_id = r.json()['scene_id']
+        secret = r.json()['secret']
+        data = f'''
+        <xml>
+            <ToUserName><![CDATA[toUser]]></ToUserName>
+            <FromUserName><![CDATA[{open_id}]]></FromUserName>
+            <CreateTime>123456789</CreateTime>
+            <MsgType><![CDATA[event]]></MsgType>
+            <Event><![CDATA[subscribe]]></Event>
+            <EventKey><![CDATA[qrscene_{scene_id}]]></EventKey>
+            <Ticket><![CDATA[TICKET]]></Ticket>
+        </xml>
+        '''
+        r = self.post('/callback', data, params=CallbackTest.sign(self.token))
+        assert r.status_code == 200
+        r = self.get(f'/scene/{scene_id}', params={'secret': secret})
+        assert r.status_code == 200
+        r = self.post('/login/exchange', {'login_token': r.json()['login_token']})
+        assert r.status_code == 200
+        return open_id, r.json()['sendkey']
+
+    def hold_messages(self, receiver, sendkey):
+        """Hold messages of the receiver for a daily digest, so none is pushed to wechat."""
+        form = {'receiver': receiver, 'sendkey': sendkey, 'digest_interval': '1440'}
+        r = self.post('/preferences', data=form)
+        assert r.status_code == 200
+
+    def send(self, receiver, title, **form):
+        """Post a message, returns its token."""
+        r = self.post('/message', data={'receiver': receiver, 'title': title, **form})
+        assert r.status_code == 200
+        return r.json()['token']
+
 
 class CallbackTest(TestCase):
     @staticmethod
@@ -44,6 +89,19 @@ class CallbackTest(TestCase):
         assert r.status_code == 200
         assert r.text == echostr
 
+    def test_callback_negotiate_default_account(self):
+        params = CallbackTest.sign(self.token)
+        params['echostr'] = 'should_echo_this'
+        r = self.get('/callback/default', params=params)
+        assert r.status_code == 200
+        assert r.text == 'should_echo_this'
+
+    def test_callback_unknown_account(self):
+        params = CallbackTest.sign(self.token)
+        params['echostr'] = '123'
+        r = self.get('/callback/unknown', params=params)
+        assert r.status_code == 404
+
     def test_callback_missing_params(self):
         assert self.get('/callback').status_code == 400
 
@@ -67,10 +125,14 @@ class SceneTest(TestCase):
         # create
         r = self.post('/scene')
         assert r.status_code == 200
-        assert list(r.json().keys()) == ['qr_url', 'scene_id', 'ticket']
-        # not found
+        assert list(r.json().keys()) == ['qr_url', 'scene_id', 'secret', 'ticket']
         scene_id = r.json()['scene_id']
-        r = self.get(f'/scene/{scene_id}')
+        secret = r.json()['secret']
+        # bad secret
+        r = self.get(f'/scene/{scene_id}', params={'secret': 'bad'})
+        assert r.status_code == 401
+        # not found
+        r = self.get(f'/scene/{scene_id}', params={'secret': secret})
         assert r.status_code == 404
         assert r.json() == {}
         # callback to register
@@ -88,10 +150,28 @@ class SceneTest(TestCase):
         r = self.post('/callback', data, params=CallbackTest.sign(self.token))
         assert r.status_code == 200
         assert r.text == ''
-        # now we should get openid
-        r = self.get(f'/scene/{scene_id}')
+        # now we should get a login token, only once
+        r = self.get(f'/scene/{scene_id}', params={'secret': secret})
+        assert r.status_code == 200
+        login_token = r.json()['login_token']
+        r = self.get(f'/scene/{scene_id}', params={'secret': secret})
+        assert r.status_code == 404
+        # exchange for the openid, only once
+        r = self.post('/login/exchange', {'login_token': login_token})
         assert r.status_code == 200
-        assert r.json() == {'openID': 'UserOpenID'}
+        assert r.json()['openID'] == 'UserOpenID'
+        assert 'sendkey' in r.json()
+        r = self.post('/login/exchange', {'login_token': login_token})
+        assert r.status_code == 401
+
+    def test_scene_long_poll_timeout(self):
+        r = self.post('/scene')
+        scene_id = r.json()['scene_id']
+        secret = r.json()['secret']
+        start = time.time()
+        r = self.get(f'/scene/{scene_id}', params={'secret': secret, 'wait': 1})
+        assert r.status_code == 404
+        assert time.time() - start >= 1
 
 
 class MessageTest(TestCase):
@@ -108,8 +188,530 @@ class MessageTest(TestCase):
         assert r.status_code == 400  # bad request
         assert r.json()['errmsg'] == 'OpenID illegal'
 
+    def test_post_message_failure_not_replayed(self):
+        form = {
+            'title': 'TEST_TITLE',
+            'receiver': 'open_id'
+        }
+        headers = {'Idempotency-Key': str(uuid.uuid4())}
+        for _ in range(2):
+            r = self.post('/message', data=form, headers=headers)
+            assert r.status_code == 400
+            assert r.json()['errmsg'] == 'OpenID illegal'
+
+    def test_post_message_with_bad_escalation(self):
+        form = {
+            'title': 'TEST_TITLE',
+            'receiver': 'open_id',
+            'escalate_after': '10',
+            'escalate_steps': '0',
+        }
+        r = self.post('/message', data=form)
+        assert r.status_code == 400
+
+    def test_post_message_with_bad_dedup_window(self):
+        form = {
+            'title': 'TEST_TITLE',
+            'receiver': 'open_id',
+            'dedup_key': 'disk-full',
+            'dedup_window': '0',
+        }
+        r = self.post('/message', data=form)
+        assert r.status_code == 400
+
+    def test_post_message_with_internal_ack_webhook(self):
+        for url in ['http://127.0.0.1:8088/api/v2/message', 'http://169.254.169.254/latest',
+                    'http://192.168.1.1/', 'ftp://example.com/']:
+            form = {'title': 'TEST_TITLE', 'receiver': 'open_id', 'ack_webhook': url}
+            r = self.post('/message', data=form)
+            assert r.status_code == 400, url
+
+    def test_post_message_with_bad_priority(self):
+        form = {
+            'title': 'TEST_TITLE',
+            'receiver': 'open_id',
+            'priority': 'urgent',
+        }
+        r = self.post('/message', data=form)
+        assert r.status_code == 400
+
+    def test_get_digest_not_found(self):
+        u = uuid.uuid4()
+        r = self.get(f'/digest/{u}')
+        assert r.status_code == 404
+        assert r.json() == {'messages': []}
+
     def test_get_message_not_found(self):
         u = uuid.uuid4()
         r = self.get(f'/message/{u}')
         assert r.status_code == 404
         assert r.json() == {}
+
+    def test_get_message_not_found_html(self):
+        u = uuid.uuid4()
+        r = self.get(f'/message/{u}', headers={'Accept': 'text/html'})
+        assert r.status_code == 404
+        assert r.headers['Content-Type'].startswith('text/html')
+
+    def test_ack_message_bad_token(self):
+        u = uuid.uuid4()
+        r = self.post(f'/message/{u}/ack', data={'by': 'open_id', 'ack': 'bad'})
+        assert r.status_code == 401
+
+    def test_create_webhook_bad_sendkey(self):
+        form = {'receiver': 'open_id', 'sendkey': 'bad', 'url': 'https://example.com/hook'}
+        r = self.post('/webhooks', data=form)
+        assert r.status_code == 401
+
+    def test_save_preferences_bad_sendkey(self):
+        form = {'receiver': 'open_id', 'sendkey': 'bad', 'quiet_start': '22:00', 'quiet_end': '07:00'}
+        r = self.post('/preferences', data=form)
+        assert r.status_code == 401
+
+    def test_save_channels_bad_sendkey(self):
+        body = {'receiver': 'open_id', 'sendkey': 'bad', 'channels': [{'kind': 'custom'}]}
+        r = self.put('/preferences/channels', json=body)
+        assert r.status_code == 401
+
+    def test_register_email_bad_sendkey(self):
+        form = {'receiver': 'open_id', 'sendkey': 'bad', 'address': 'someone@example.com'}
+        r = self.post('/preferences/email', data=form)
+        assert r.status_code == 401
+
+    def test_post_hook_bad_sendkey(self):
+        r = self.post('/hook/bad/grafana', json={'state': 'alerting', 'ruleName': 'CPU'})
+        assert r.status_code == 401
+
+    def test_post_alertmanager_bad_sendkey(self):
+        body = {'status': 'firing', 'groupKey': '{}:{alertname="A"}', 'alerts': [
+            {'status': 'firing', 'labels': {'alertname': 'A'}, 'annotations': {}, 'fingerprint': 'f'}]}
+        r = self.post('/alertmanager/bad', json=body)
+        assert r.status_code == 401
+
+    def test_save_hook_mapping_bad_sendkey(self):
+        body = {'receiver': 'open_id', 'sendkey': 'bad', 'mapping': {'title': '{{ $.title }}'}}
+        r = self.put('/preferences/hooks/grafana', json=body)
+        assert r.status_code == 401
+
+    def test_list_messages_bad_sendkey(self):
+        r = self.get('/messages', params={'receiver': 'open_id', 'sendkey': 'bad'})
+        assert r.status_code == 401
+
+
+class MessageLifetimeTest(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.receiver, self.sendkey = self.login()
+        self.hold_messages(self.receiver, self.sendkey)
+
+    def test_revoke_message(self):
+        token = self.send(self.receiver, 'sent by mistake', body='secret')
+        assert self.get(f'/message/{token}').status_code == 200
+        r = self.delete(f'/message/{token}', params={'sendkey': 'bad'})
+        assert r.status_code == 401
+        r = self.delete(f'/message/{token}', params={'sendkey': self.sendkey})
+        assert r.status_code == 200
+        r = self.get(f'/message/{token}')
+        assert r.status_code == 410
+        assert r.json() == {}
+        r = self.get(f'/message/{token}', headers={'Accept': 'text/html'})
+        assert r.status_code == 410
+        assert 'secret' not in r.text
+        r = self.get(f'/message/{token}/status', params={'sendkey': self.sendkey})
+        assert r.json()['revoked_time'] is not None
+
+    def test_message_expires(self):
+        expires_at = int(time.time()) + 2
+        token = self.send(self.receiver, 'short lived', expires_at=str(expires_at))
+        r = self.get(f'/message/{token}')
+        assert r.status_code == 200
+        assert r.json()['expires_at'] == expires_at
+        time.sleep(3)
+        assert self.get(f'/message/{token}').status_code == 410
+
+    def test_message_expires_in_the_past(self):
+        form = {'receiver': self.receiver, 'title': 'late', 'expires_at': str(int(time.time()) - 1)}
+        r = self.post('/message', data=form)
+        assert r.status_code == 400
+
+
+class IdempotencyTest(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.receiver, self.sendkey = self.login()
+        self.hold_messages(self.receiver, self.sendkey)
+
+    def test_replay(self):
+        headers = {'Idempotency-Key': str(uuid.uuid4())}
+        form = {'receiver': self.receiver, 'title': 'once'}
+        r = self.post('/message', data=form, headers=headers)
+        assert r.status_code == 200
+        assert 'Idempotent-Replayed' not in r.headers
+        token = r.json()['token']
+        r = self.post('/message', data=form, headers=headers)
+        assert r.status_code == 200
+        assert r.headers['Idempotent-Replayed'] == 'true'
+        assert r.json()['token'] == token
+        r = self.get('/messages', params={'receiver': self.receiver, 'sendkey': self.sendkey})
+        assert [m['token'] for m in r.json()['messages']] == [token]
+
+    def test_key_reused_for_another_message(self):
+        key = str(uuid.uuid4())
+        r = self.post('/message', data={'receiver': self.receiver, 'title': 'first',
+                                        'idempotency_key': key})
+        assert r.status_code == 200
+        r = self.post('/message', data={'receiver': self.receiver, 'title': 'second',
+                                        'idempotency_key': key})
+        assert r.status_code == 400
+
+
+class DedupTest(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.receiver, self.sendkey = self.login()
+        self.hold_messages(self.receiver, self.sendkey)
+
+    def test_duplicates_counted(self):
+        key = f'disk-full-{uuid.uuid4().hex}'
+        first = self.send(self.receiver, 'disk full', dedup_key=key, dedup_window='5')
+        for duplicates in [1, 2]:
+            r = self.post('/message', data={'receiver': self.receiver, 'title': 'disk full',
+                                            'dedup_key': key})
+            assert r.status_code == 200
+            assert r.json() == {'token': first, 'duplicates': duplicates}
+        r = self.get(f'/message/{first}')
+        assert r.json()['duplicates'] == 2
+        assert abs(r.json()['dedup_window'] - 5 * 60) <= 1
+        # only the first one is saved
+        r = self.get('/messages', params={'receiver': self.receiver, 'sendkey': self.sendkey})
+        assert [m['token'] for m in r.json()['messages']] == [first]
+
+    def test_dedup_by_receiver(self):
+        other, other_sendkey = self.login()
+        self.hold_messages(other, other_sendkey)
+        key = f'disk-full-{uuid.uuid4().hex}'
+        first = self.send(self.receiver, 'disk full', dedup_key=key)
+        assert self.send(other, 'disk full', dedup_key=key) != first
+
+
+class MessageViewTest(TestCase):
+    ANDROID_UA = 'Mozilla/5.0 (Linux; Android 10; Pixel 3) AppleWebKit/537.36 Chrome/83.0 Mobile Safari/537.36'
+    MAC_UA = 'Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_5) AppleWebKit/605.1.15 Safari/605.1.15'
+
+    def setUp(self):
+        super().setUp()
+        self.receiver, self.sendkey = self.login()
+        self.hold_messages(self.receiver, self.sendkey)
+
+    def status(self, token):
+        r = self.get(f'/message/{token}/status', params={'sendkey': self.sendkey})
+        assert r.status_code == 200
+        return r.json()
+
+    def view(self, token, ua):
+        r = self.get(f'/message/{token}', headers={'Accept': 'text/html', 'User-Agent': ua})
+        assert r.status_code == 200
+
+    def test_views_recorded(self):
+        token = self.send(self.receiver, 'viewed')
+        views = self.status(token)['views']
+        assert views['count'] == 0
+        assert views['first_viewed_time'] is None
+        # api calls are not views
+        assert self.get(f'/message/{token}').status_code == 200
+        assert self.status(token)['views']['count'] == 0
+        self.view(token, self.ANDROID_UA)
+        self.view(token, self.ANDROID_UA)
+        self.view(token, self.MAC_UA)
+        views = self.status(token)['views']
+        assert views['count'] == 3
+        assert views['clients'] == {'mobile': 2, 'desktop': 1}
+        assert views['first_viewed_time'] <= views['last_viewed_time']
+
+    def test_status_bad_sendkey(self):
+        token = self.send(self.receiver, 'private')
+        r = self.get(f'/message/{token}/status', params={'sendkey': 'bad'})
+        assert r.status_code == 401
+
+
+class WebhookTest(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.receiver, self.sendkey = self.login()
+        self.hold_messages(self.receiver, self.sendkey)
+
+    def create(self, url, **form):
+        form = {'receiver': self.receiver, 'sendkey': self.sendkey, 'url': url, **form}
+        return self.post('/webhooks', data=form)
+
+    def test_webhook_internal_url(self):
+        for url in ['http://127.0.0.1:6379/', 'http://169.254.169.254/latest/meta-data',
+                    'http://10.0.0.1/hook', 'http://[::1]/', 'http://localhost/', 'gopher://example.com/']:
+            r = self.create(url)
+            assert r.status_code == 400, url
+
+    def test_webhook_lifecycle(self):
+        r = self.create('https://example.com/hook', events='viewed')
+        assert r.status_code == 200
+        webhook_id = r.json()['id']
+        r = self.get('/webhooks', params={'receiver': self.receiver, 'sendkey': self.sendkey})
+        assert [w['id'] for w in r.json()['webhooks']] == [webhook_id]
+        # the first view is an event
+        token = self.send(self.receiver, 'watched')
+        r = self.get(f'/message/{token}', headers={'Accept': 'text/html'})
+        assert r.status_code == 200
+        r = self.get(f'/webhooks/{webhook_id}/deliveries', params={'sendkey': self.sendkey})
+        assert r.status_code == 200
+        deliveries = r.json()['deliveries']
+        assert [(d['message_id'], d['event']) for d in deliveries] == [(token, 'viewed')]
+        r = self.delete(f'/webhooks/{webhook_id}', params={'sendkey': 'bad'})
+        assert r.status_code == 401
+        r = self.delete(f'/webhooks/{webhook_id}', params={'sendkey': self.sendkey})
+        assert r.status_code == 200
+        r = self.get('/webhooks', params={'receiver': self.receiver, 'sendkey': self.sendkey})
+        assert r.json()['webhooks'] == []
+
+    def test_webhook_unknown_event(self):
+        r = self.create('https://example.com/hook', events='sent,exploded')
+        assert r.status_code == 400
+
+
+class MessageSenderTest(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.receiver, self.sendkey = self.login()
+        self.hold_messages(self.receiver, self.sendkey)
+
+    def test_send_with_sendkey(self):
+        token = self.send(self.receiver, 'signed', sendkey=self.sendkey)
+        r = self.get(f'/message/{token}')
+        assert r.status_code == 200
+
+    def test_send_sender_rate_limited(self):
+        # drain the bucket of the send key by other requests authorised by it
+        for _ in range(100):
+            r = self.get('/messages', params={'receiver': self.receiver, 'sendkey': self.sendkey})
+            if r.status_code == 429:
+                break
+        assert r.status_code == 429
+        r = self.post('/message', data={'receiver': self.receiver, 'title': 'signed', 'sendkey': self.sendkey})
+        assert r.status_code == 429
+        assert int(r.headers['Retry-After']) >= 1
+        # the receiver itself is not limited
+        self.send(self.receiver, 'unsigned')
+
+    def test_send_with_bad_sendkey(self):
+        other, other_sendkey = self.login()
+        for sendkey in ['bad', other_sendkey]:
+            r = self.post('/message', data={'receiver': self.receiver, 'title': 'signed', 'sendkey': sendkey})
+            assert r.status_code == 401
+
+
+class MessageListTest(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.receiver, self.sendkey = self.login()
+        self.hold_messages(self.receiver, self.sendkey)
+
+    def list(self, **params):
+        return self.get('/messages', params={
+            'receiver': self.receiver, 'sendkey': self.sendkey, **params})
+
+    def test_list_messages_pages(self):
+        tokens = [self.send(self.receiver, f'title {i}') for i in range(3)]
+        r = self.list(limit=2)
+        assert r.status_code == 200
+        first = r.json()
+        assert len(first['messages']) == 2
+        assert first['next'] is not None
+        r = self.list(limit=2, before=first['next'])
+        assert r.status_code == 200
+        second = r.json()
+        assert len(second['messages']) == 1
+        assert second['next'] is None
+        listed = [m['token'] for m in first['messages'] + second['messages']]
+        assert sorted(listed) == sorted(tokens)
+        times = [m['created_time'] for m in first['messages'] + second['messages']]
+        assert times == sorted(times, reverse=True)
+
+    def test_list_messages_of_other_receiver(self):
+        other, _ = self.login()
+        self.send(self.receiver, 'mine')
+        r = self.get('/messages', params={'receiver': other, 'sendkey': self.sendkey})
+        assert r.status_code == 401
+
+    def test_list_messages_bad_cursor(self):
+        r = self.list(before='garbage')
+        assert r.status_code == 400
+
+
+class MessageSearchTest(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.receiver, self.sendkey = self.login()
+        self.hold_messages(self.receiver, self.sendkey)
+
+    def search(self, q):
+        return self.get('/messages/search', params={
+            'receiver': self.receiver, 'sendkey': self.sendkey, 'q': q})
+
+    def test_search_messages(self):
+        token = self.send(self.receiver, 'disk full on db1', body='<b>/var</b> is 99% used')
+        self.send(self.receiver, 'backup done')
+        r = self.search('disk')
+        assert r.status_code == 200
+        messages = r.json()['messages']
+        assert [m['token'] for m in messages] == [token]
+        assert messages[0]['title'] == '<mark>disk</mark> full on db1'
+        r = self.search('var')
+        snippet = r.json()['messages'][0]['snippet']
+        assert '&lt;b&gt;/<mark>var</mark>&lt;/b&gt;' in snippet
+        assert self.search('nothing').json()['messages'] == []
+
+    def test_search_messages_markers_in_text(self):
+        self.send(self.receiver, 'cpu \x02high\x03 load', body='\x02x\x03')
+        r = self.search('cpu')
+        assert r.status_code == 200
+        title = r.json()['messages'][0]['title']
+        assert title == '<mark>cpu</mark> high load'
+
+    def test_search_messages_revoked(self):
+        token = self.send(self.receiver, 'revoked secret')
+        r = self.delete(f'/message/{token}', params={'sendkey': self.sendkey})
+        assert r.status_code == 200
+        assert self.search('secret').json()['messages'] == []
+
+    def test_search_messages_empty_query(self):
+        assert self.search(' ').status_code == 400
+
+
+class HookTest(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.receiver, self.sendkey = self.login()
+        self.hold_messages(self.receiver, self.sendkey)
+        mapping = {'title': '[{{ $.state }}] {{ $.ruleName }}', 'body': '{{ $.message }}'}
+        body = {'receiver': self.receiver, 'sendkey': self.sendkey, 'mapping': mapping}
+        r = self.put('/preferences/hooks/grafana', json=body)
+        assert r.status_code == 200
+
+    def test_post_hook(self):
+        payload = {'state': 'alerting', 'ruleName': 'CPU', 'message': 'load is high'}
+        r = self.post(f'/hook/{self.sendkey}/grafana', json=payload)
+        assert r.status_code == 200
+        token = r.json()['token']
+        r = self.get('/messages', params={'receiver': self.receiver, 'sendkey': self.sendkey})
+        assert r.status_code == 200
+        assert [(m['token'], m['title']) for m in r.json()['messages']] == [(token, '[alerting] CPU')]
+
+    def test_post_hook_unknown_mapping(self):
+        r = self.post(f'/hook/{self.sendkey}/nagios', json={'state': 'alerting'})
+        assert r.status_code == 404
+
+    def test_post_hook_bad_json(self):
+        r = self.post(f'/hook/{self.sendkey}/grafana', data='not json')
+        assert r.status_code == 400
+
+
+class AlertmanagerTest(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.receiver, self.sendkey = self.login()
+        self.hold_messages(self.receiver, self.sendkey)
+
+    def notify(self, status, group_key, **params):
+        alert = {'status': status, 'labels': {'alertname': 'DiskFull'},
+                 'annotations': {'summary': 'disk is full'}, 'fingerprint': 'f1'}
+        body = {'status': status, 'groupKey': group_key, 'groupLabels': {'alertname': 'DiskFull'},
+                'commonLabels': alert['labels'], 'alerts': [alert]}
+        return self.post(f'/alertmanager/{self.sendkey}', params=params, json=body)
+
+    def escalation(self, token):
+        r = self.get(f'/message/{token}/status', params={'sendkey': self.sendkey})
+        assert r.status_code == 200
+        return r.json()['escalation']
+
+    def test_firing_resolved(self):
+        group_key = f'{{}}:{{alertname="DiskFull",run="{uuid.uuid4().hex}"}}'
+        r = self.notify('firing', group_key)
+        assert r.status_code == 200
+        firing = r.json()['token']
+        # repeated notifications are counted on the first one
+        r = self.notify('firing', group_key)
+        assert r.status_code == 200
+        assert r.json()['token'] == firing
+        r = self.notify('resolved', group_key)
+        assert r.status_code == 200
+        resolved = r.json()['token']
+        assert resolved != firing
+        r = self.get('/messages', params={'receiver': self.receiver, 'sendkey': self.sendkey})
+        assert r.status_code == 200
+        titles = {m['token']: m['title'] for m in r.json()['messages']}
+        assert titles == {firing: '[FIRING:1] DiskFull', resolved: '[RESOLVED] DiskFull'}
+
+    def test_resolved_cancels_escalation(self):
+        group_key = f'{{}}:{{alertname="DiskFull",run="{uuid.uuid4().hex}"}}'
+        r = self.notify('firing', group_key, escalate_after='30')
+        assert r.status_code == 200
+        firing = r.json()['token']
+        assert self.escalation(firing)['status'] == 'pending'
+        r = self.notify('resolved', group_key, escalate_after='30')
+        assert r.status_code == 200
+        resolved = r.json()['token']
+        assert self.escalation(firing)['status'] == 'cancelled'
+        # only firing messages are escalated
+        assert self.escalation(resolved) is None
+
+    def test_no_alerts(self):
+        body = {'status': 'firing', 'groupKey': '{}:{}', 'alerts': []}
+        r = self.post(f'/alertmanager/{self.sendkey}', json=body)
+        assert r.status_code == 400
+
+
+class ChannelTest(TestCase):
+    def setUp(self):
+        super().setUp()
+        self.receiver, self.sendkey = self.login()
+
+    def save(self, channels):
+        body = {'receiver': self.receiver, 'sendkey': self.sendkey, 'channels': channels}
+        return self.put('/preferences/channels', json=body)
+
+    def test_channels_order(self):
+        channels = [
+            {'kind': 'webhook', 'account': None, 'target': 'https://example.com/hook'},
+            {'kind': 'custom', 'account': None, 'target': None},
+            {'kind': 'template', 'account': None, 'target': None},
+        ]
+        r = self.save(channels)
+        assert r.status_code == 200
+        r = self.get('/preferences/channels', params={'receiver': self.receiver, 'sendkey': self.sendkey})
+        assert r.status_code == 200
+        assert r.json()['channels'] == channels
+        # back to the default channel
+        r = self.save([])
+        assert r.status_code == 200
+        r = self.get('/preferences/channels', params={'receiver': self.receiver, 'sendkey': self.sendkey})
+        assert r.json()['channels'] == []
+
+    def test_channels_internal_webhook(self):
+        for url in ['http://127.0.0.1:6379/', 'http://169.254.169.254/latest/meta-data',
+                    'http://localhost/', 'ftp://example.com/']:
+            r = self.save([{'kind': 'webhook', 'target': url}])
+            assert r.status_code == 400, url
+        r = self.save([{'kind': 'webhook'}])
+        assert r.status_code == 400
+
+    def test_channels_unknown_kind(self):
+        r = self.save([{'kind': 'fax'}])
+        assert r.status_code == 400
+
+
+class AdminTest(TestCase):
+    def test_quota_unauthorized(self):
+        r = self.get('/admin/quota', headers={'Authorization': 'Bearer bad'})
+        assert r.status_code == 401
+
+    def test_receiver_account_unauthorized(self):
+        r = self.post('/admin/receivers/user_id/account', data={'account': 'default'})
+        assert r.status_code == 401